@@ -1,13 +1,149 @@
-use ::amethyst::ecs::World;
+use crate::relative_timer::RelativeTimer;
+
+use ::amethyst::core::timing::Time;
+use ::amethyst::ecs::{Read, System, World, Write};
+use ::amethyst::shrev::EventChannel;
+use crossbeam_channel::{Receiver as EventReceiver, Sender as EventSender};
+use discord_rpc_client::models::{Activity, Event};
 use discord_rpc_client::Client as DiscordClient;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::*;
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
 use std::thread;
 use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// The party the player is in, displayed as "(size of max)".
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, new)]
+pub struct DiscordParty {
+    pub id: String,
+    pub size: u32,
+    pub max: u32,
+}
+
+/// Secrets enabling the "Ask to Join" and "Spectate" buttons.
+/// They are given back to the game when another player uses them.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DiscordSecrets {
+    pub match_secret: Option<String>,
+    pub join_secret: Option<String>,
+    pub spectate_secret: Option<String>,
+}
+
+/// Everything displayed in the discord rich presence.
+/// Timestamps are in seconds since the unix epoch.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DiscordActivity {
+    pub state: Option<String>,
+    pub details: Option<String>,
+    /// Displays "xx:xx elapsed".
+    pub start_timestamp: Option<u64>,
+    /// Displays "xx:xx left". Takes precedence over `start_timestamp`.
+    pub end_timestamp: Option<u64>,
+    pub large_image: Option<String>,
+    pub large_image_text: Option<String>,
+    pub small_image: Option<String>,
+    pub small_image_text: Option<String>,
+    pub party: Option<DiscordParty>,
+    pub secrets: DiscordSecrets,
+}
+
+impl DiscordActivity {
+    /// Sets the start timestamp so that discord displays the time elapsed on the timer.
+    /// The end timestamp is cleared. Clears both if the timer isn't running.
+    pub fn set_elapsed_from_timer(&mut self, timer: &RelativeTimer) {
+        self.end_timestamp = None;
+        self.start_timestamp = if timer.running {
+            Some(unix_now().saturating_sub(timer.duration().max(0.0) as u64))
+        } else {
+            None
+        };
+    }
+
+    /// Sets the end timestamp so that discord counts down the given number of seconds.
+    pub fn set_remaining(&mut self, seconds: f64) {
+        self.end_timestamp = Some(unix_now() + seconds.max(0.0) as u64);
+    }
+
+    fn apply(self, activity: Activity) -> Activity {
+        let mut a = activity;
+        if let Some(state) = self.state {
+            a = a.state(state);
+        }
+        if let Some(details) = self.details {
+            a = a.details(details);
+        }
+        if self.start_timestamp.is_some() || self.end_timestamp.is_some() {
+            let (start, end) = (self.start_timestamp, self.end_timestamp);
+            a = a.timestamps(|t| {
+                let mut tmp = t;
+                if let Some(s) = start {
+                    tmp = tmp.start(s);
+                }
+                if let Some(e) = end {
+                    tmp = tmp.end(e);
+                }
+                tmp
+            });
+        }
+        let (large_image, large_image_text, small_image, small_image_text) = (
+            self.large_image,
+            self.large_image_text,
+            self.small_image,
+            self.small_image_text,
+        );
+        a = a.assets(|ass| {
+            let mut tmp = ass;
+            if let Some(t) = large_image {
+                tmp = tmp.large_image(t);
+            }
+            if let Some(t) = large_image_text {
+                tmp = tmp.large_text(t);
+            }
+            if let Some(t) = small_image {
+                tmp = tmp.small_image(t);
+            }
+            if let Some(t) = small_image_text {
+                tmp = tmp.small_text(t);
+            }
+            tmp
+        });
+        if let Some(party) = self.party {
+            a = a.party(|p| p.id(party.id).size((party.size, party.max)));
+        }
+        let secrets = self.secrets;
+        if secrets != DiscordSecrets::default() {
+            a = a.secrets(|s| {
+                let mut tmp = s;
+                if let Some(t) = secrets.match_secret {
+                    tmp = tmp.game(t);
+                }
+                if let Some(t) = secrets.join_secret {
+                    tmp = tmp.join(t);
+                }
+                if let Some(t) = secrets.spectate_secret {
+                    tmp = tmp.spectate(t);
+                }
+                tmp
+            });
+        }
+        a
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
 
 /// Discord Rich Presence wrapper around discord_rpc_client
 /// Currently errors are not exposed by the library, so I use the log crate
 /// to display errors and only return Result<T, ()> from the methods.
+/// The activities go through a `PresenceBackend`, which is the discord client unless
+/// created using `with_backend`.
 ///
 /// Make sure to properly create your app here: https://discordapp.com/developers/applications
 ///
@@ -16,14 +152,17 @@ use std::thread::JoinHandle;
 /// fn init_discord_rich_presence() -> Result<DiscordRichPresence,()> {
 ///     DiscordRichPresence::new(498979571933380609, "Main Menu", Some("large_image"), Some("Hoppin World"), None, None);
 /// }
+///
+/// // Or with the full activity:
+/// let mut activity = DiscordActivity::default();
+/// activity.details = Some(String::from("Speedrunning map1"));
+/// activity.party = Some(DiscordParty::new(String::from("lobby1"), 2, 4));
+/// activity.set_elapsed_from_timer(&world.read_resource::<RelativeTimer>());
+/// DiscordRichPresence::with_activity(498979571933380609, activity);
 /// ```
 pub struct DiscordRichPresence {
-    pub rpc: DiscordClient,
-    state: String,
-    large_image: Option<String>,
-    large_image_text: Option<String>,
-    small_image: Option<String>,
-    small_image_text: Option<String>,
+    backend: Box<dyn PresenceBackend>,
+    activity: DiscordActivity,
 }
 
 impl DiscordRichPresence {
@@ -35,94 +174,1008 @@ impl DiscordRichPresence {
         small_image: Option<String>,
         small_image_text: Option<String>,
     ) -> Self {
-        let rpc = DiscordClient::new(app_id);
-        DiscordRichPresence {
-            rpc,
-            state,
-            large_image,
-            large_image_text,
-            small_image,
-            small_image_text,
+        DiscordRichPresence::with_activity(
+            app_id,
+            DiscordActivity {
+                state: Some(state),
+                large_image,
+                large_image_text,
+                small_image,
+                small_image_text,
+                ..Default::default()
+            },
+        )
+    }
+
+    pub fn with_activity(app_id: u64, activity: DiscordActivity) -> Self {
+        DiscordRichPresence::with_backend(Box::new(DiscordIpcBackend::new(app_id)), activity)
+    }
+
+    /// Uses a custom backend, for example a `RecordingBackend` in tests.
+    pub fn with_backend(backend: Box<dyn PresenceBackend>, activity: DiscordActivity) -> Self {
+        DiscordRichPresence { backend, activity }
+    }
+
+    /// Starts the backend and sends the activity.
+    /// Returns true if the activity was sent.
+    pub fn start(&mut self) -> bool {
+        self.backend.start();
+        self.update()
+    }
+
+    /// Reconnects the backend and sends the activity again.
+    /// Returns true if the activity was sent.
+    pub fn reconnect(&mut self) -> bool {
+        if let Err(e) = self.backend.reconnect() {
+            warn!("Failed to reconnect to discord: {}", e);
+            return false;
         }
+        self.update()
     }
 
-    pub fn start(&mut self) {
-        self.rpc.start();
-        self.update();
+    pub fn activity(&self) -> &DiscordActivity {
+        &self.activity
     }
 
-    pub fn set_state(&mut self, state: String) {
-        self.state = state;
-        self.update();
+    pub fn set_activity(&mut self, activity: DiscordActivity) -> bool {
+        self.activity = activity;
+        self.update()
     }
 
-    pub fn update(&mut self) {
-        let state = self.state.clone();
-        let large_image = self.large_image.clone();
-        let large_image_text = self.large_image_text.clone();
-        let small_image = self.small_image.clone();
-        let small_image_text = self.small_image_text.clone();
-        if let Err(e) = self.rpc.set_activity(|a| {
-            a.state(state).assets(|ass| {
-                let mut tmp = ass;
-                if let Some(t) = large_image {
-                    tmp = tmp.large_image(t);
-                }
-                if let Some(t) = large_image_text {
-                    tmp = tmp.large_text(t);
-                }
-                if let Some(t) = small_image {
-                    tmp = tmp.small_image(t);
-                }
-                if let Some(t) = small_image_text {
-                    tmp = tmp.small_text(t);
-                }
-                tmp
-            })
-        }) {
+    pub fn set_state(&mut self, state: String) -> bool {
+        self.activity.state = Some(state);
+        self.update()
+    }
+
+    pub fn set_details(&mut self, details: Option<String>) -> bool {
+        self.activity.details = details;
+        self.update()
+    }
+
+    pub fn set_timestamps(&mut self, start: Option<u64>, end: Option<u64>) -> bool {
+        self.activity.start_timestamp = start;
+        self.activity.end_timestamp = end;
+        self.update()
+    }
+
+    pub fn set_party(&mut self, party: Option<DiscordParty>) -> bool {
+        self.activity.party = party;
+        self.update()
+    }
+
+    pub fn set_secrets(&mut self, secrets: DiscordSecrets) -> bool {
+        self.activity.secrets = secrets;
+        self.update()
+    }
+
+    /// Incoming discord events will be sent through this channel.
+    /// Must be called before `start`.
+    pub fn set_event_sender(&mut self, sender: EventSender<DiscordEvent>) {
+        self.backend.set_event_sender(sender);
+    }
+
+    /// Accepts or refuses a `DiscordEvent::JoinRequest`. Returns true on success.
+    pub fn respond_join_request(&mut self, user_id: &str, accept: bool) -> bool {
+        if let Err(e) = self.backend.respond_join_request(user_id, accept) {
+            error!("Failed to respond to discord join request: {}", e);
+            false
+        } else {
+            true
+        }
+    }
+
+    /// Sends the activity. Returns true on success.
+    pub fn update(&mut self) -> bool {
+        if let Err(e) = self.backend.set_activity(&self.activity) {
             error!("Failed to set discord rich presence state: {}", e);
+            false
+        } else {
+            true
         }
     }
 }
 
 impl Drop for DiscordRichPresence {
     fn drop(&mut self) {
-        if let Err(e) = self.rpc.clear_activity() {
+        if let Err(e) = self.backend.clear_activity() {
             eprintln!("Failed to clear discord rich presence activity {:?}", e);
         }
     }
 }
 
+/// Where the `DiscordRichPresence` sends the activities.
+pub trait PresenceBackend: Send {
+    fn start(&mut self);
+    fn set_activity(&mut self, activity: &DiscordActivity) -> Result<(), String>;
+    fn clear_activity(&mut self) -> Result<(), String>;
+    /// Called periodically after a failure to send an activity, until an activity is sent again.
+    fn reconnect(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+    /// Where to send the incoming events. Backends that don't receive events can ignore it.
+    /// Called once, before `start`.
+    fn set_event_sender(&mut self, _sender: EventSender<DiscordEvent>) {}
+    fn respond_join_request(&mut self, _user_id: &str, _accept: bool) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// Sends the activities to the discord client running on this computer.
+pub struct DiscordIpcBackend {
+    rpc: DiscordClient,
+    /// The event handlers are registered.
+    forwarding_events: bool,
+}
+
+impl DiscordIpcBackend {
+    pub fn new(app_id: u64) -> Self {
+        DiscordIpcBackend {
+            rpc: DiscordClient::new(app_id),
+            forwarding_events: false,
+        }
+    }
+
+    /// Asks discord for the join, spectate and join request events.
+    /// Needs to be done again for each new connection.
+    /// Fails if discord can't be reached.
+    fn subscribe(&mut self) -> Result<(), String> {
+        if !self.forwarding_events {
+            return Ok(());
+        }
+        for event in vec![
+            Event::ActivityJoin,
+            Event::ActivitySpectate,
+            Event::ActivityJoinRequest,
+        ] {
+            let name = format!("{:?}", event);
+            self.rpc
+                .subscribe(event, |s| s)
+                .map_err(|e| format!("Failed to subscribe to discord event {}: {}", name, e))?;
+        }
+        Ok(())
+    }
+}
+
+impl PresenceBackend for DiscordIpcBackend {
+    fn start(&mut self) {
+        self.rpc.start();
+        if let Err(e) = self.subscribe() {
+            error!("{}", e);
+        }
+    }
+
+    fn set_activity(&mut self, activity: &DiscordActivity) -> Result<(), String> {
+        let activity = activity.clone();
+        self.rpc
+            .set_activity(|a| activity.apply(a))
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+
+    fn clear_activity(&mut self) -> Result<(), String> {
+        self.rpc
+            .clear_activity()
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+
+    fn reconnect(&mut self) -> Result<(), String> {
+        // The client reconnects by itself in its own thread, but the subscriptions are lost
+        // with the old connection. Subscribing again also tells if discord is back.
+        self.subscribe()
+    }
+
+    /// Forwards the join, spectate and join request events to the sender.
+    /// The client keeps every handler it is given, so they are only registered here.
+    fn set_event_sender(&mut self, sender: EventSender<DiscordEvent>) {
+        if self.forwarding_events {
+            error!("The discord event sender can only be set once.");
+            return;
+        }
+        self.forwarding_events = true;
+        let tx = sender.clone();
+        self.rpc.on_activity_join(move |ctx| {
+            if let Some(secret) = ctx.event["secret"].as_str() {
+                let _ = tx.send(DiscordEvent::Join {
+                    secret: secret.to_string(),
+                });
+            }
+        });
+        let tx = sender.clone();
+        self.rpc.on_activity_spectate(move |ctx| {
+            if let Some(secret) = ctx.event["secret"].as_str() {
+                let _ = tx.send(DiscordEvent::Spectate {
+                    secret: secret.to_string(),
+                });
+            }
+        });
+        self.rpc.on_activity_join_request(move |ctx| {
+            match serde_json::from_value::<DiscordUser>(ctx.event["user"].clone()) {
+                Ok(user) => {
+                    let _ = sender.send(DiscordEvent::JoinRequest(user));
+                }
+                Err(e) => error!("Failed to read discord join request: {}", e),
+            }
+        });
+    }
+
+    fn respond_join_request(&mut self, user_id: &str, accept: bool) -> Result<(), String> {
+        let user_id = user_id
+            .parse::<u64>()
+            .map_err(|e| format!("Invalid discord user id {}: {}", user_id, e))?;
+        if accept {
+            self.rpc
+                .send_activity_join_invite(user_id)
+                .map(|_| ())
+                .map_err(|e| e.to_string())
+        } else {
+            self.rpc
+                .close_activity_request(user_id)
+                .map(|_| ())
+                .map_err(|e| e.to_string())
+        }
+    }
+}
+
+/// A call received by a `RecordingBackend`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PresenceCall {
+    Start,
+    SetActivity(DiscordActivity),
+    ClearActivity,
+    Reconnect,
+    RespondJoinRequest(String, bool),
+}
+
+/// Keeps the calls in memory instead of sending them to discord.
+/// Clones share the same records, so you can keep one to inspect what the `DiscordRichPresence` sent.
+/// Calls made while disconnected fail and are not recorded.
+/// Like the discord client, every event sender it is given receives the events.
+#[derive(Debug, Clone)]
+pub struct RecordingBackend {
+    calls: Arc<Mutex<Vec<PresenceCall>>>,
+    connected: Arc<AtomicBool>,
+    events: Arc<Mutex<Vec<EventSender<DiscordEvent>>>>,
+}
+
+impl Default for RecordingBackend {
+    fn default() -> Self {
+        RecordingBackend {
+            calls: Arc::new(Mutex::new(Vec::new())),
+            connected: Arc::new(AtomicBool::new(true)),
+            events: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+}
+
+impl RecordingBackend {
+    pub fn calls(&self) -> Vec<PresenceCall> {
+        self.calls
+            .lock()
+            .expect("Failed to acquire mutex lock for the recorded presence calls.")
+            .clone()
+    }
+
+    /// The activities successfully sent, in order.
+    pub fn activities(&self) -> Vec<DiscordActivity> {
+        self.calls()
+            .into_iter()
+            .filter_map(|c| match c {
+                PresenceCall::SetActivity(a) => Some(a),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Simulates discord being closed or reopened.
+    pub fn set_connected(&self, connected: bool) {
+        self.connected.store(connected, Ordering::SeqCst);
+    }
+
+    pub fn connected(&self) -> bool {
+        self.connected.load(Ordering::SeqCst)
+    }
+
+    /// Simulates an event coming from discord.
+    /// Returns false if no event sender was set.
+    pub fn emit(&self, event: DiscordEvent) -> bool {
+        let senders = self
+            .events
+            .lock()
+            .expect("Failed to acquire mutex lock for the recording backend event senders.");
+        !senders.is_empty() && senders.iter().all(|s| s.send(event.clone()).is_ok())
+    }
+
+    fn record(&self, call: PresenceCall) -> Result<(), String> {
+        if !self.connected() {
+            return Err(String::from("Not connected to discord."));
+        }
+        self.calls
+            .lock()
+            .expect("Failed to acquire mutex lock for the recorded presence calls.")
+            .push(call);
+        Ok(())
+    }
+}
+
+impl PresenceBackend for RecordingBackend {
+    fn start(&mut self) {
+        // Start is recorded even when disconnected, like the real client starts before connecting.
+        self.calls
+            .lock()
+            .expect("Failed to acquire mutex lock for the recorded presence calls.")
+            .push(PresenceCall::Start);
+    }
+
+    fn set_activity(&mut self, activity: &DiscordActivity) -> Result<(), String> {
+        self.record(PresenceCall::SetActivity(activity.clone()))
+    }
+
+    fn clear_activity(&mut self) -> Result<(), String> {
+        self.record(PresenceCall::ClearActivity)
+    }
+
+    fn reconnect(&mut self) -> Result<(), String> {
+        self.record(PresenceCall::Reconnect)
+    }
+
+    fn set_event_sender(&mut self, sender: EventSender<DiscordEvent>) {
+        self.events
+            .lock()
+            .expect("Failed to acquire mutex lock for the recording backend event senders.")
+            .push(sender);
+    }
+
+    fn respond_join_request(&mut self, user_id: &str, accept: bool) -> Result<(), String> {
+        self.record(PresenceCall::RespondJoinRequest(
+            user_id.to_string(),
+            accept,
+        ))
+    }
+}
+
+/// The state of the connection between the `DiscordThreadHolder` thread and discord.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiscordConnectionStatus {
+    /// The thread started but did not send anything yet.
+    Connecting,
+    /// The last activity was sent successfully.
+    Connected,
+    /// Sending the last activity failed. The thread is trying to reconnect.
+    Disconnected,
+    /// The thread is not running.
+    Stopped,
+}
+
+impl Default for DiscordConnectionStatus {
+    fn default() -> Self {
+        DiscordConnectionStatus::Stopped
+    }
+}
+
+/// Runs the `DiscordRichPresence` in its own thread, so that the game never waits on discord.
+/// When sending an activity fails, the thread reconnects periodically, waiting twice as long after each failure.
+/// The thread is stopped when this is dropped.
 pub struct DiscordThreadHolder {
-    pub thread: JoinHandle<()>,
+    thread: Option<JoinHandle<()>>,
     pub sender: Mutex<Sender<DiscordThreadMessage>>,
+    status: Arc<Mutex<DiscordConnectionStatus>>,
+    events: EventReceiver<DiscordEvent>,
 }
 
 impl DiscordThreadHolder {
-    pub fn new(mut presence: DiscordRichPresence) -> Self {
+    pub fn new(presence: DiscordRichPresence) -> Self {
+        DiscordThreadHolder::with_reconnect_backoff(
+            presence,
+            Duration::from_secs(2),
+            Duration::from_secs(60),
+        )
+    }
+
+    /// `min_backoff` is the time to wait before the first reconnection attempt,
+    /// `max_backoff` is the maximum time between two attempts.
+    pub fn with_reconnect_backoff(
+        mut presence: DiscordRichPresence,
+        min_backoff: Duration,
+        max_backoff: Duration,
+    ) -> Self {
         let (tx, rx) = channel();
+        let (event_tx, event_rx) = crossbeam_channel::unbounded();
+        presence.set_event_sender(event_tx);
+        let status = Arc::new(Mutex::new(DiscordConnectionStatus::Connecting));
+        let thread_status = status.clone();
         let thread = thread::spawn(move || {
-            presence.start();
+            let set_status = |connected: bool| {
+                let new_status = if connected {
+                    DiscordConnectionStatus::Connected
+                } else {
+                    DiscordConnectionStatus::Disconnected
+                };
+                if let Ok(mut status) = thread_status.lock() {
+                    *status = new_status;
+                }
+                connected
+            };
+            let mut connected = set_status(presence.start());
+            let mut backoff = min_backoff;
+            let mut next_reconnect = Instant::now() + backoff;
             loop {
-                match rx.recv() {
+                // Reconnect on time, even when messages keep arriving.
+                let now = Instant::now();
+                if !connected && now >= next_reconnect {
+                    connected = set_status(presence.reconnect());
+                    if connected {
+                        info!("Connected to discord.");
+                        backoff = min_backoff;
+                    } else {
+                        backoff = (backoff * 2).min(max_backoff);
+                    }
+                    next_reconnect = Instant::now() + backoff;
+                    continue;
+                }
+
+                let message = if connected {
+                    rx.recv().map_err(|_| RecvTimeoutError::Disconnected)
+                } else {
+                    rx.recv_timeout(next_reconnect - now)
+                };
+                let sent = match message {
                     Ok(DiscordThreadMessage::Update) => presence.update(),
                     Ok(DiscordThreadMessage::SetState(state)) => presence.set_state(state),
-                    Err(_) => return,
+                    Ok(DiscordThreadMessage::SetDetails(details)) => presence.set_details(details),
+                    Ok(DiscordThreadMessage::SetTimestamps(start, end)) => {
+                        presence.set_timestamps(start, end)
+                    }
+                    Ok(DiscordThreadMessage::SetParty(party)) => presence.set_party(party),
+                    Ok(DiscordThreadMessage::SetSecrets(secrets)) => presence.set_secrets(secrets),
+                    Ok(DiscordThreadMessage::SetActivity(activity)) => {
+                        presence.set_activity(activity)
+                    }
+                    Ok(DiscordThreadMessage::RespondJoinRequest { user_id, accept }) => {
+                        // Fails for invalid users too, only the activity updates tell if we are connected.
+                        presence.respond_join_request(&user_id, accept);
+                        continue;
+                    }
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    Ok(DiscordThreadMessage::Shutdown) | Err(RecvTimeoutError::Disconnected) => {
+                        break;
+                    }
+                };
+                if sent && !connected {
+                    info!("Connected to discord.");
+                    backoff = min_backoff;
+                } else if !sent && connected {
+                    warn!("Lost the connection to discord, reconnecting.");
+                    next_reconnect = Instant::now() + backoff;
                 }
+                connected = set_status(sent);
+            }
+            // Clear the activity before reporting that we stopped.
+            drop(presence);
+            if let Ok(mut status) = thread_status.lock() {
+                *status = DiscordConnectionStatus::Stopped;
             }
         });
         Self {
-            thread,
+            thread: Some(thread),
             sender: Mutex::new(tx),
+            status,
+            events: event_rx,
+        }
+    }
+
+    /// Sends a message to the discord thread.
+    /// Returns false if the thread is not running anymore.
+    pub fn send(&self, message: DiscordThreadMessage) -> bool {
+        match self.sender.lock() {
+            Ok(sender) => sender.send(message).is_ok(),
+            Err(_) => {
+                error!("Failed to acquire mutex lock for DiscordThreadHolder sender handle");
+                false
+            }
+        }
+    }
+
+    /// The events received from discord since the last call.
+    pub fn poll_events(&self) -> Vec<DiscordEvent> {
+        self.events.try_iter().collect()
+    }
+
+    pub fn status(&self) -> DiscordConnectionStatus {
+        self.status
+            .lock()
+            .map(|s| *s)
+            .unwrap_or(DiscordConnectionStatus::Stopped)
+    }
+
+    /// Clears the activity, stops the thread and waits for it to finish.
+    /// Does nothing if the thread was already stopped.
+    pub fn shutdown(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            self.send(DiscordThreadMessage::Shutdown);
+            if thread.join().is_err() {
+                error!("The discord thread panicked.");
+            }
         }
     }
 }
 
+impl Drop for DiscordThreadHolder {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
 pub enum DiscordThreadMessage {
-    Update, SetState(String),
+    Update,
+    SetState(String),
+    SetDetails(Option<String>),
+    /// Start and end timestamps, in seconds since the unix epoch.
+    SetTimestamps(Option<u64>, Option<u64>),
+    SetParty(Option<DiscordParty>),
+    SetSecrets(DiscordSecrets),
+    /// Replaces the whole activity.
+    SetActivity(DiscordActivity),
+    /// Answers a `DiscordEvent::JoinRequest`.
+    RespondJoinRequest {
+        user_id: String,
+        accept: bool,
+    },
+    /// Clears the activity and stops the thread.
+    Shutdown,
 }
 
 /// Changes the discord rich presence state, if present in the world.
 pub fn set_discord_state(state: String, world: &mut World) {
-    world.fetch_mut::<DiscordThreadHolder>().sender.lock().expect("Failed to acquire mutex lock for DiscordThreadHolder sender handle").send(DiscordThreadMessage::SetState(state)).expect("Failed to send state update message through DiscordThreadHolder's sender");
+    if let Some(holder) = world.try_fetch::<DiscordThreadHolder>() {
+        if !holder.send(DiscordThreadMessage::SetState(state)) {
+            warn!("Failed to send state update message through DiscordThreadHolder's sender");
+        }
+    }
+}
+
+/// The activity that should be displayed in discord.
+/// Change it from your states (for example in `on_start`), the `DiscordPresenceSystem` takes care of sending it.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DiscordPresence {
+    /// None leaves the last sent activity untouched.
+    pub activity: Option<DiscordActivity>,
+}
+
+impl DiscordPresence {
+    pub fn set(&mut self, activity: DiscordActivity) {
+        self.activity = Some(activity);
+    }
+}
+
+/// Sends the `DiscordPresence` resource to discord when it changes.
+/// Updates are rate limited to `max_updates` per `window` seconds (5 per 20 seconds for discord);
+/// changes made while rate limited are sent as soon as possible.
+/// Does nothing if there is no `DiscordThreadHolder` in the world.
+pub struct DiscordPresenceSystem {
+    pub max_updates: usize,
+    pub window: f64,
+    last_sent: Option<DiscordActivity>,
+    sent_times: VecDeque<f64>,
+}
+
+impl Default for DiscordPresenceSystem {
+    fn default() -> Self {
+        DiscordPresenceSystem::new(5, 20.0)
+    }
+}
+
+impl DiscordPresenceSystem {
+    pub fn new(max_updates: usize, window: f64) -> Self {
+        DiscordPresenceSystem {
+            max_updates,
+            window,
+            last_sent: None,
+            sent_times: VecDeque::new(),
+        }
+    }
+
+    fn rate_limited(&mut self, now: f64) -> bool {
+        while let Some(t) = self.sent_times.front().cloned() {
+            if now - t >= self.window {
+                self.sent_times.pop_front();
+            } else {
+                break;
+            }
+        }
+        self.sent_times.len() >= self.max_updates
+    }
+}
+
+impl<'a> System<'a> for DiscordPresenceSystem {
+    type SystemData = (
+        Read<'a, DiscordPresence>,
+        Option<Read<'a, DiscordThreadHolder>>,
+        Read<'a, Time>,
+    );
+
+    fn run(&mut self, (presence, holder, time): Self::SystemData) {
+        let holder = match holder {
+            Some(h) => h,
+            None => return,
+        };
+        let activity = match presence.activity {
+            Some(ref a) => a,
+            None => return,
+        };
+        if self.last_sent.as_ref() == Some(activity) {
+            return;
+        }
+        let now = time.absolute_real_time_seconds();
+        if self.rate_limited(now) {
+            return;
+        }
+        if holder.send(DiscordThreadMessage::SetActivity(activity.clone())) {
+            self.sent_times.push_back(now);
+        } else if self.last_sent.is_none() {
+            warn!("The discord thread is not running, the discord presence will not be updated.");
+        }
+        // Not retried if the thread is gone, it will not come back.
+        self.last_sent = Some(activity.clone());
+    }
+}
+
+/// Emitted by the `DiscordConnectionSystem` when the connection to discord changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiscordConnectionEvent {
+    Connected,
+    Disconnected,
+}
+
+/// The connection status of the `DiscordThreadHolder`, updated each frame by the `DiscordConnectionSystem`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DiscordConnection {
+    pub status: DiscordConnectionStatus,
+}
+
+/// Copies the status of the `DiscordThreadHolder` into the `DiscordConnection` resource,
+/// and emits `DiscordConnectionEvent`s when it changes.
+#[derive(Default)]
+pub struct DiscordConnectionSystem;
+
+impl<'a> System<'a> for DiscordConnectionSystem {
+    type SystemData = (
+        Option<Read<'a, DiscordThreadHolder>>,
+        Write<'a, DiscordConnection>,
+        Write<'a, EventChannel<DiscordConnectionEvent>>,
+    );
+
+    fn run(&mut self, (holder, mut connection, mut events): Self::SystemData) {
+        let status = holder
+            .map(|h| h.status())
+            .unwrap_or(DiscordConnectionStatus::Stopped);
+        if status == connection.status {
+            return;
+        }
+        if status == DiscordConnectionStatus::Connected {
+            events.single_write(DiscordConnectionEvent::Connected);
+        } else if connection.status == DiscordConnectionStatus::Connected {
+            events.single_write(DiscordConnectionEvent::Disconnected);
+        }
+        connection.status = status;
+    }
+}
+
+/// A discord user asking to join our game.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, new)]
+pub struct DiscordUser {
+    pub id: String,
+    pub username: String,
+    pub discriminator: String,
+    #[serde(default)]
+    pub avatar: Option<String>,
+}
+
+/// Events received from discord.
+/// The secrets are the ones set in the `DiscordSecrets` of the activity of the player we join.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DiscordEvent {
+    /// The player accepted an invitation or their join request was accepted.
+    Join { secret: String },
+    /// The player wants to spectate a game.
+    Spectate { secret: String },
+    /// Someone clicked "Ask to Join" on our profile.
+    /// Answer with `DiscordThreadMessage::RespondJoinRequest`.
+    JoinRequest(DiscordUser),
+}
+
+/// Writes the events received by the `DiscordThreadHolder` into the `EventChannel<DiscordEvent>`.
+#[derive(Default)]
+pub struct DiscordEventSystem;
+
+impl<'a> System<'a> for DiscordEventSystem {
+    type SystemData = (
+        Option<Read<'a, DiscordThreadHolder>>,
+        Write<'a, EventChannel<DiscordEvent>>,
+    );
+
+    fn run(&mut self, (holder, mut events): Self::SystemData) {
+        if let Some(holder) = holder {
+            events.iter_write(holder.poll_events());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::discord::*;
+
+    use amethyst::ecs::RunNow;
+    use amethyst::prelude::*;
+
+    use std::thread::sleep;
+    use std::time::{Duration, Instant};
+
+    /// Waits until the backend recorded `count` activities.
+    fn wait_activities(backend: &RecordingBackend, count: usize) -> Vec<DiscordActivity> {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while backend.activities().len() < count && Instant::now() < deadline {
+            sleep(Duration::from_millis(5));
+        }
+        backend.activities()
+    }
+
+    fn holder_with(backend: &RecordingBackend) -> DiscordThreadHolder {
+        let mut activity = DiscordActivity::default();
+        activity.state = Some(String::from("Main Menu"));
+        DiscordThreadHolder::new(DiscordRichPresence::with_backend(
+            Box::new(backend.clone()),
+            activity,
+        ))
+    }
+
+    #[test]
+    fn thread_sends_updates() {
+        let backend = RecordingBackend::default();
+        let holder = holder_with(&backend);
+        holder.send(DiscordThreadMessage::SetParty(Some(DiscordParty::new(
+            String::from("lobby"),
+            1,
+            4,
+        ))));
+
+        let activities = wait_activities(&backend, 2);
+        assert_eq!(backend.calls()[0], PresenceCall::Start);
+        assert_eq!(activities[0].state, Some(String::from("Main Menu")));
+        assert_eq!(activities[1].party.as_ref().unwrap().max, 4);
+        assert_eq!(activities[1].state, Some(String::from("Main Menu")));
+    }
+
+    #[test]
+    fn thread_survives_disconnect() {
+        let backend = RecordingBackend::default();
+        let holder = holder_with(&backend);
+        wait_activities(&backend, 1);
+
+        backend.set_connected(false);
+        holder.send(DiscordThreadMessage::SetDetails(Some(String::from("map1"))));
+        sleep(Duration::from_millis(50));
+        assert_eq!(backend.activities().len(), 1);
+
+        backend.set_connected(true);
+        assert!(holder.send(DiscordThreadMessage::Update));
+        let activities = wait_activities(&backend, 2);
+        assert_eq!(activities[1].details, Some(String::from("map1")));
+    }
+
+    #[test]
+    fn presence_system_sends_changes_only() {
+        let backend = RecordingBackend::default();
+        let mut world = World::new();
+        let mut system = DiscordPresenceSystem::default();
+        System::setup(&mut system, &mut world);
+        world.insert(holder_with(&backend));
+        wait_activities(&backend, 1);
+
+        let mut activity = DiscordActivity::default();
+        activity.state = Some(String::from("In Game"));
+        world
+            .write_resource::<DiscordPresence>()
+            .set(activity.clone());
+        system.run_now(&world);
+        system.run_now(&world);
+
+        let activities = wait_activities(&backend, 2);
+        sleep(Duration::from_millis(50));
+        assert_eq!(backend.activities().len(), 2);
+        assert_eq!(activities[1], activity);
+    }
+
+    fn wait_status(holder: &DiscordThreadHolder, status: DiscordConnectionStatus) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while holder.status() != status && Instant::now() < deadline {
+            sleep(Duration::from_millis(5));
+        }
+        assert_eq!(holder.status(), status);
+    }
+
+    #[test]
+    fn automatic_reconnect() {
+        let backend = RecordingBackend::default();
+        backend.set_connected(false);
+        let holder = DiscordThreadHolder::with_reconnect_backoff(
+            DiscordRichPresence::with_backend(
+                Box::new(backend.clone()),
+                DiscordActivity::default(),
+            ),
+            Duration::from_millis(10),
+            Duration::from_millis(20),
+        );
+        wait_status(&holder, DiscordConnectionStatus::Disconnected);
+
+        backend.set_connected(true);
+        wait_status(&holder, DiscordConnectionStatus::Connected);
+        assert!(backend.calls().contains(&PresenceCall::Reconnect));
+        assert_eq!(backend.activities().len(), 1);
+    }
+
+    #[test]
+    fn shutdown_joins_thread() {
+        let backend = RecordingBackend::default();
+        let mut holder = holder_with(&backend);
+        wait_status(&holder, DiscordConnectionStatus::Connected);
+
+        holder.shutdown();
+        assert_eq!(holder.status(), DiscordConnectionStatus::Stopped);
+        assert_eq!(backend.calls().last(), Some(&PresenceCall::ClearActivity));
+        assert!(!holder.send(DiscordThreadMessage::Update));
+    }
+
+    #[test]
+    fn connection_events() {
+        let backend = RecordingBackend::default();
+        let mut world = World::new();
+        let mut system = DiscordConnectionSystem;
+        System::setup(&mut system, &mut world);
+        let mut reader = world
+            .write_resource::<EventChannel<DiscordConnectionEvent>>()
+            .register_reader();
+        world.insert(holder_with(&backend));
+        wait_status(
+            &world.read_resource::<DiscordThreadHolder>(),
+            DiscordConnectionStatus::Connected,
+        );
+        system.run_now(&world);
+        world.write_resource::<DiscordThreadHolder>().shutdown();
+        system.run_now(&world);
+
+        let events = world
+            .read_resource::<EventChannel<DiscordConnectionEvent>>()
+            .read(&mut reader)
+            .cloned()
+            .collect::<Vec<_>>();
+        assert_eq!(
+            events,
+            vec![
+                DiscordConnectionEvent::Connected,
+                DiscordConnectionEvent::Disconnected
+            ]
+        );
+        assert_eq!(
+            world.read_resource::<DiscordConnection>().status,
+            DiscordConnectionStatus::Stopped
+        );
+    }
+
+    #[test]
+    fn join_request_round_trip() {
+        let backend = RecordingBackend::default();
+        let mut world = World::new();
+        let mut system = DiscordEventSystem;
+        System::setup(&mut system, &mut world);
+        let mut reader = world
+            .write_resource::<EventChannel<DiscordEvent>>()
+            .register_reader();
+        let holder = holder_with(&backend);
+        wait_status(&holder, DiscordConnectionStatus::Connected);
+        world.insert(holder);
+
+        let user = DiscordUser::new(
+            String::from("1234"),
+            String::from("jojo"),
+            String::from("0001"),
+            None,
+        );
+        assert!(backend.emit(DiscordEvent::JoinRequest(user.clone())));
+        assert!(backend.emit(DiscordEvent::Join {
+            secret: String::from("lobby1")
+        }));
+        system.run_now(&world);
+
+        let events = world
+            .read_resource::<EventChannel<DiscordEvent>>()
+            .read(&mut reader)
+            .cloned()
+            .collect::<Vec<_>>();
+        assert_eq!(
+            events,
+            vec![
+                DiscordEvent::JoinRequest(user),
+                DiscordEvent::Join {
+                    secret: String::from("lobby1")
+                }
+            ]
+        );
+
+        world.read_resource::<DiscordThreadHolder>().send(
+            DiscordThreadMessage::RespondJoinRequest {
+                user_id: String::from("1234"),
+                accept: true,
+            },
+        );
+        world.write_resource::<DiscordThreadHolder>().shutdown();
+        assert!(backend.calls().contains(&PresenceCall::RespondJoinRequest(
+            String::from("1234"),
+            true
+        )));
+    }
+
+    #[test]
+    fn events_received_once_after_reconnects() {
+        let backend = RecordingBackend::default();
+        let mut world = World::new();
+        let mut system = DiscordEventSystem;
+        System::setup(&mut system, &mut world);
+        let mut reader = world
+            .write_resource::<EventChannel<DiscordEvent>>()
+            .register_reader();
+        backend.set_connected(false);
+        let holder = DiscordThreadHolder::with_reconnect_backoff(
+            DiscordRichPresence::with_backend(
+                Box::new(backend.clone()),
+                DiscordActivity::default(),
+            ),
+            Duration::from_millis(10),
+            Duration::from_millis(20),
+        );
+        wait_status(&holder, DiscordConnectionStatus::Disconnected);
+        backend.set_connected(true);
+        wait_status(&holder, DiscordConnectionStatus::Connected);
+
+        // Lose the connection again.
+        backend.set_connected(false);
+        holder.send(DiscordThreadMessage::Update);
+        wait_status(&holder, DiscordConnectionStatus::Disconnected);
+        backend.set_connected(true);
+        wait_status(&holder, DiscordConnectionStatus::Connected);
+        let reconnects = backend
+            .calls()
+            .into_iter()
+            .filter(|c| *c == PresenceCall::Reconnect)
+            .count();
+        assert!(reconnects >= 2);
+        world.insert(holder);
+
+        let join = DiscordEvent::Join {
+            secret: String::from("lobby1"),
+        };
+        let spectate = DiscordEvent::Spectate {
+            secret: String::from("lobby1"),
+        };
+        assert!(backend.emit(join.clone()));
+        assert!(backend.emit(spectate.clone()));
+        system.run_now(&world);
+
+        let events = world
+            .read_resource::<EventChannel<DiscordEvent>>()
+            .read(&mut reader)
+            .cloned()
+            .collect::<Vec<_>>();
+        assert_eq!(events, vec![join, spectate]);
+    }
+
+    #[test]
+    fn presence_rate_limit() {
+        let mut system = DiscordPresenceSystem::new(2, 20.0);
+        assert!(!system.rate_limited(0.0));
+        system.sent_times.push_back(0.0);
+        system.sent_times.push_back(1.0);
+        assert!(system.rate_limited(19.0));
+        assert!(!system.rate_limited(20.0));
+    }
 }
@@ -0,0 +1,69 @@
+use hyper::StatusCode;
+
+use std::fmt;
+
+/// Errors returned by the fallible parts of this crate.
+#[derive(Debug)]
+pub enum Error {
+    /// The TLS backend failed to initialize.
+    Tls(String),
+    /// The data could not be serialized to json.
+    Serialization(serde_json::Error),
+    /// The request could not be created, usually because of an invalid url or header.
+    InvalidRequest(String),
+    /// The request could not be sent or the response body could not be read.
+    Transport(hyper::Error),
+    /// The server answered with a non-success status code.
+    Status(StatusCode),
+    /// The response body is not valid json for the expected type.
+    Deserialization(serde_json::Error),
+    /// No response was received before the request timeout.
+    Timeout,
+    /// The request was cancelled before completing.
+    Cancelled,
+    /// An io error, for example when creating the runtime driving the requests.
+    Io(std::io::Error),
+}
+
+impl Error {
+    /// Transport errors, timeouts, server errors and rate limiting are worth retrying.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::Transport(_) | Error::Timeout => true,
+            Error::Status(s) => s.is_server_error() || *s == StatusCode::TOO_MANY_REQUESTS,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Tls(e) => write!(f, "TLS initialization failed: {}", e),
+            Error::Serialization(e) => write!(f, "Failed to serialize data to json: {}", e),
+            Error::InvalidRequest(e) => write!(f, "Failed to create the http request: {}", e),
+            Error::Transport(e) => write!(f, "Http transport error: {}", e),
+            Error::Status(s) => write!(f, "Http request failed with status: {}", s),
+            Error::Deserialization(e) => {
+                write!(f, "Failed to deserialize the http response body: {}", e)
+            }
+            Error::Timeout => write!(f, "Http request timed out"),
+            Error::Cancelled => write!(f, "Http request was cancelled"),
+            Error::Io(e) => write!(f, "Io error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<hyper::Error> for Error {
+    fn from(e: hyper::Error) -> Self {
+        Error::Transport(e)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
@@ -0,0 +1,1004 @@
+use crate::auth::Auth;
+use crate::error::Error;
+
+use ::amethyst::ecs::{Read, System, World, Write};
+use ::amethyst::CallbackQueue;
+
+use crossbeam_channel::Sender;
+
+use dirty::Dirty;
+
+use hyper::client::HttpConnector;
+use hyper::header::CONTENT_LENGTH;
+use hyper::{Body, Chunk, Client, Method, Request, Response};
+use hyper_tls::HttpsConnector;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::prelude::future::{Either, Loop};
+use tokio::prelude::{future, Future, Stream};
+use tokio::runtime::Runtime;
+use tokio::timer::{Delay, Timeout};
+
+/// A function executed on the main thread, with full access to the `World`.
+/// Same signature as the callbacks accepted by amethyst's `CallbackQueue`.
+pub type WorldCallback = Box<dyn FnOnce(&mut World) + Send>;
+
+/// Identifies a request sent through the `HttpClientResource`.
+/// Ids are unique for a given `HttpClientResource`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct RequestId(pub u64);
+
+/// How failed requests are retried.
+/// Only errors for which `Error::is_retryable` is true are retried.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, new)]
+pub struct RetryPolicy {
+    /// The number of retries after the first attempt.
+    pub max_retries: u32,
+    /// The time to wait before the first retry.
+    pub initial_backoff: Duration,
+    /// The maximum time to wait between two retries.
+    pub max_backoff: Duration,
+    /// The backoff is multiplied by this value after each retry.
+    #[new(value = "2.0")]
+    pub multiplier: f64,
+}
+
+impl RetryPolicy {
+    /// Never retry.
+    pub fn none() -> Self {
+        RetryPolicy::new(0, Duration::from_secs(0), Duration::from_secs(0))
+    }
+
+    /// The time to wait before the given retry. The first retry is 0.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let secs = duration_secs(self.initial_backoff) * self.multiplier.powi(retry as i32);
+        let secs = secs.min(duration_secs(self.max_backoff)).max(0.0);
+        Duration::from_millis((secs * 1000.0) as u64)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy::none()
+    }
+}
+
+pub(crate) fn duration_secs(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) / 1_000_000_000.0
+}
+
+/// Everything needed to send, and resend, an http request.
+///
+/// Usage:
+/// ```rs
+/// let request = HttpRequest::get("https://example.com/maps")
+///     .query("page", "2")
+///     .query("search", "bhop & surf")
+///     .header("Accept", "application/json")
+///     .retry(RetryPolicy::new(3, Duration::from_millis(500), Duration::from_secs(5)));
+/// ```
+#[derive(Debug, Clone)]
+pub struct HttpRequest {
+    pub method: Method,
+    /// The url, without the query parameters added through `query`.
+    pub url: String,
+    /// Query parameters, not yet encoded.
+    pub query: Vec<(String, String)>,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    /// Maximum duration of each attempt. None waits forever.
+    pub timeout: Option<Duration>,
+    pub retry: RetryPolicy,
+}
+
+impl HttpRequest {
+    /// Creates an empty request with a 30 seconds timeout and no retries.
+    pub fn new(method: Method, url: &str) -> Self {
+        HttpRequest {
+            method,
+            url: url.to_string(),
+            query: Vec::new(),
+            headers: Vec::new(),
+            body: Vec::new(),
+            timeout: Some(Duration::from_secs(30)),
+            retry: RetryPolicy::none(),
+        }
+    }
+
+    pub fn get(url: &str) -> Self {
+        HttpRequest::new(Method::GET, url)
+    }
+
+    pub fn post(url: &str) -> Self {
+        HttpRequest::new(Method::POST, url)
+    }
+
+    pub fn put(url: &str) -> Self {
+        HttpRequest::new(Method::PUT, url)
+    }
+
+    pub fn patch(url: &str) -> Self {
+        HttpRequest::new(Method::PATCH, url)
+    }
+
+    pub fn delete(url: &str) -> Self {
+        HttpRequest::new(Method::DELETE, url)
+    }
+
+    pub fn post_json<T: Serialize>(url: &str, data: &T) -> Result<Self, Error> {
+        HttpRequest::post(url).json(data)
+    }
+
+    pub fn put_json<T: Serialize>(url: &str, data: &T) -> Result<Self, Error> {
+        HttpRequest::put(url).json(data)
+    }
+
+    pub fn patch_json<T: Serialize>(url: &str, data: &T) -> Result<Self, Error> {
+        HttpRequest::patch(url).json(data)
+    }
+
+    /// Serializes `data` as the json body of the request.
+    pub fn json<T: Serialize>(self, data: &T) -> Result<Self, Error> {
+        let body = serde_json::to_vec(data).map_err(Error::Serialization)?;
+        Ok(self.header("Content-Type", "application/json").body(body))
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Adds a query parameter. It will be url encoded when building the request.
+    pub fn query(mut self, name: &str, value: &str) -> Self {
+        self.query.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Sets the `Authorization: Bearer` header.
+    pub fn bearer_auth(self, token: &str) -> Self {
+        self.header("Authorization", &format!("Bearer {}", token))
+    }
+
+    pub fn body(mut self, body: Vec<u8>) -> Self {
+        self.body = body;
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn has_header(&self, name: &str) -> bool {
+        self.headers
+            .iter()
+            .any(|(n, _)| n.eq_ignore_ascii_case(name))
+    }
+
+    /// The url including the encoded query parameters.
+    pub fn full_url(&self) -> String {
+        if self.query.is_empty() {
+            return self.url.clone();
+        }
+        let query = self
+            .query
+            .iter()
+            .map(|(name, value)| format!("{}={}", url_encode(name), url_encode(value)))
+            .collect::<Vec<_>>()
+            .join("&");
+        let separator = if self.url.contains('?') { '&' } else { '?' };
+        format!("{}{}{}", self.url, separator, query)
+    }
+
+    /// Creates the hyper `Request` for one attempt.
+    pub fn build(&self) -> Result<Request<Body>, Error> {
+        let mut builder = Request::builder();
+        builder.method(self.method.clone()).uri(self.full_url().as_str());
+        for (name, value) in &self.headers {
+            builder.header(name.as_str(), value.as_str());
+        }
+        builder
+            .body(Body::from(self.body.clone()))
+            .map_err(|e| Error::InvalidRequest(e.to_string()))
+    }
+}
+
+/// Percent encodes everything except the unreserved characters of RFC 3986.
+pub fn url_encode(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                out.push(byte as char)
+            }
+            _ => out.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    out
+}
+
+/// Handle to a request in flight.
+/// Cancelling it guarantees that its callback is dropped without being called,
+/// even if the response already arrived but was not processed by the `CallbackQueue` yet.
+#[derive(Clone)]
+pub struct RequestHandle {
+    id: RequestId,
+    slot: Arc<dyn Cancellable>,
+}
+
+impl RequestHandle {
+    pub fn id(&self) -> RequestId {
+        self.id
+    }
+
+    pub fn cancel(&self) {
+        self.slot.cancel();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.slot.is_cancelled()
+    }
+}
+
+trait Cancellable: Send + Sync {
+    fn cancel(&self);
+    fn is_cancelled(&self) -> bool;
+}
+
+/// Holds the user callback until the request completes or is cancelled.
+struct CallbackSlot<F> {
+    cancelled: AtomicBool,
+    callback: Mutex<Option<F>>,
+}
+
+impl<F> CallbackSlot<F> {
+    fn new(callback: F) -> Self {
+        CallbackSlot {
+            cancelled: AtomicBool::new(false),
+            callback: Mutex::new(Some(callback)),
+        }
+    }
+
+    fn take(&self) -> Option<F> {
+        self.callback
+            .lock()
+            .expect("Failed to acquire mutex lock for the http callback.")
+            .take()
+    }
+}
+
+impl<F: Send> Cancellable for CallbackSlot<F> {
+    fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+        // Drop the callback right away.
+        self.take();
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+/// Resource holding an http client and the runtime driving its requests.
+/// Responses are fully received, deserialized and then handed back to you
+/// on the main thread through the `CallbackQueue`.
+///
+/// Usage:
+/// ```rs
+/// let http = HttpClientResource::new(&world.read_resource::<CallbackQueue>())?;
+/// world.insert(http);
+///
+/// let handle = world.write_resource::<HttpClientResource>().get_json::<Scores, _>(
+///     "https://example.com/scores",
+///     |_id, result, world| match result {
+///         Ok(scores) => world.insert(scores),
+///         Err(e) => error!("Failed to fetch the scores: {}", e),
+///     },
+/// );
+/// // If the state is popped before the scores arrive:
+/// handle.cancel();
+/// ```
+pub struct HttpClientResource {
+    client: Client<HttpsConnector<HttpConnector>, Body>,
+    runtime: Runtime,
+    callbacks: Sender<WorldCallback>,
+    next_id: u64,
+    auth_token: Option<String>,
+}
+
+impl HttpClientResource {
+    /// Creates the client, sending results through the provided `CallbackQueue`.
+    /// Fails if the TLS backend or the runtime can't be initialized.
+    pub fn new(callback_queue: &CallbackQueue) -> Result<Self, Error> {
+        Self::with_sender(callback_queue.send_handle())
+    }
+
+    /// Creates the client, sending results through any callback channel.
+    /// Useful when you want to execute the callbacks yourself.
+    pub fn with_sender(callbacks: Sender<WorldCallback>) -> Result<Self, Error> {
+        Ok(HttpClientResource {
+            client: https_client()?,
+            runtime: Runtime::new()?,
+            callbacks,
+            next_id: 0,
+            auth_token: None,
+        })
+    }
+
+    /// Sets the token sent as a bearer `Authorization` header with every request
+    /// that doesn't already have one. This is kept in sync with the `Auth` resource by the `HttpAuthSystem`.
+    pub fn set_auth_token(&mut self, token: Option<String>) {
+        self.auth_token = token;
+    }
+
+    pub fn auth_token(&self) -> Option<&String> {
+        self.auth_token.as_ref()
+    }
+
+    /// Sends a GET request and deserializes the json response into `T`.
+    pub fn get_json<T, F>(&mut self, url: &str, on_done: F) -> RequestHandle
+    where
+        T: DeserializeOwned + Send + 'static,
+        F: FnOnce(RequestId, Result<T, Error>, &mut World) + Send + 'static,
+    {
+        let request = HttpRequest::get(url).header("Accept", "application/json");
+        self.request_json(request, on_done)
+    }
+
+    /// Sends `data` as json in a POST request and deserializes the json response into `Res`.
+    /// Fails right away if `data` can't be serialized.
+    pub fn post_json<Req, Res, F>(
+        &mut self,
+        url: &str,
+        data: &Req,
+        on_done: F,
+    ) -> Result<RequestHandle, Error>
+    where
+        Req: Serialize,
+        Res: DeserializeOwned + Send + 'static,
+        F: FnOnce(RequestId, Result<Res, Error>, &mut World) + Send + 'static,
+    {
+        Ok(self.request_json(HttpRequest::post_json(url, data)?, on_done))
+    }
+
+    /// Sends the request and deserializes the json response into `T`.
+    pub fn request_json<T, F>(&mut self, request: HttpRequest, on_done: F) -> RequestHandle
+    where
+        T: DeserializeOwned + Send + 'static,
+        F: FnOnce(RequestId, Result<T, Error>, &mut World) + Send + 'static,
+    {
+        let request = self.authorize(request);
+        let id = self.next_request_id();
+        let slot = Arc::new(CallbackSlot::new(on_done));
+        let callbacks = self.callbacks.clone();
+        let done_slot = slot.clone();
+        let future = send_with_retries(self.client.clone(), request, slot.clone())
+            .and_then(read_json_body::<T>)
+            .then(move |result| {
+                deliver(&callbacks, id, done_slot, result);
+                Ok(())
+            });
+        self.runtime.spawn(future);
+        RequestHandle { id, slot }
+    }
+
+    /// Sends the request and returns the raw response body.
+    /// Useful for requests without a json response, like most DELETE requests.
+    pub fn send<F>(&mut self, request: HttpRequest, on_done: F) -> RequestHandle
+    where
+        F: FnOnce(RequestId, Result<Vec<u8>, Error>, &mut World) + Send + 'static,
+    {
+        self.download_request(request, on_done).0
+    }
+
+    /// Downloads the body of the url without blocking.
+    /// The returned `DownloadProgress` can be polled each frame to display the progress,
+    /// and `on_done` receives the complete body once it has been received.
+    ///
+    /// Usage:
+    /// ```rs
+    /// let (handle, progress) = http.download("https://example.com/mods/pack.zip", |_id, result, world| {
+    ///     // Write the mod pack to disk...
+    /// });
+    /// // Later, in a system:
+    /// if let Some(fraction) = progress.fraction() {
+    ///     text.text = format!("{:.0}%", fraction * 100.0);
+    /// }
+    /// ```
+    pub fn download<F>(&mut self, url: &str, on_done: F) -> (RequestHandle, DownloadProgress)
+    where
+        F: FnOnce(RequestId, Result<Vec<u8>, Error>, &mut World) + Send + 'static,
+    {
+        self.download_request(HttpRequest::get(url).timeout(None), on_done)
+    }
+
+    /// Same as `download`, using a custom request.
+    /// The timeout of the request only applies until the response headers are received.
+    pub fn download_request<F>(
+        &mut self,
+        request: HttpRequest,
+        on_done: F,
+    ) -> (RequestHandle, DownloadProgress)
+    where
+        F: FnOnce(RequestId, Result<Vec<u8>, Error>, &mut World) + Send + 'static,
+    {
+        let request = self.authorize(request);
+        let id = self.next_request_id();
+        let slot = Arc::new(CallbackSlot::new(on_done));
+        let progress = DownloadProgress::default();
+        let chunk_progress = progress.clone();
+        let done_progress = progress.clone();
+        let callbacks = self.callbacks.clone();
+        let done_slot = slot.clone();
+        let chunk_slot = slot.clone();
+        let future = send_with_retries(self.client.clone(), request, slot.clone())
+            .and_then(move |response| {
+                let total = response
+                    .headers()
+                    .get(CONTENT_LENGTH)
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(|v| v.parse::<u64>().ok());
+                chunk_progress.set_total(total);
+                response.into_body().map_err(Error::Transport).fold(
+                    Vec::new(),
+                    move |mut data, chunk| {
+                        // Stop downloading as soon as possible once cancelled.
+                        if chunk_slot.is_cancelled() {
+                            return Err(Error::Cancelled);
+                        }
+                        data.extend_from_slice(&chunk);
+                        chunk_progress.add_received(chunk.len() as u64);
+                        Ok(data)
+                    },
+                )
+            })
+            .then(move |result| {
+                done_progress.finish();
+                deliver(&callbacks, id, done_slot, result);
+                Ok(())
+            });
+        self.runtime.spawn(future);
+        (RequestHandle { id, slot }, progress)
+    }
+
+    fn authorize(&self, request: HttpRequest) -> HttpRequest {
+        match self.auth_token {
+            Some(ref token) if !request.has_header("Authorization") => request.bearer_auth(token),
+            _ => request,
+        }
+    }
+
+    fn next_request_id(&mut self) -> RequestId {
+        self.next_id += 1;
+        RequestId(self.next_id)
+    }
+}
+
+/// Sends the request, retrying according to its `RetryPolicy`.
+/// Non-success status codes are returned as `Error::Status`.
+fn send_with_retries(
+    client: Client<HttpsConnector<HttpConnector>, Body>,
+    request: HttpRequest,
+    slot: Arc<dyn Cancellable>,
+) -> Box<dyn Future<Item = Response<Body>, Error = Error> + Send> {
+    Box::new(future::loop_fn(0, move |retry: u32| {
+        if slot.is_cancelled() {
+            return Either::A(future::err(Error::Cancelled));
+        }
+        let request_attempt = match request.build() {
+            Ok(r) => r,
+            Err(e) => return Either::A(future::err(e)),
+        };
+        let response = client
+            .request(request_attempt)
+            .map_err(Error::Transport)
+            .and_then(|response| {
+                let status = response.status();
+                if status.is_success() {
+                    Ok(response)
+                } else {
+                    Err(Error::Status(status))
+                }
+            });
+        let response: Box<dyn Future<Item = Response<Body>, Error = Error> + Send> =
+            match request.timeout {
+                Some(timeout) => Box::new(Timeout::new(response, timeout).map_err(|e| {
+                    if e.is_elapsed() {
+                        Error::Timeout
+                    } else if e.is_inner() {
+                        e.into_inner().unwrap_or(Error::Timeout)
+                    } else {
+                        // The timer only fails when the runtime is shutting down.
+                        Error::Cancelled
+                    }
+                })),
+                None => Box::new(response),
+            };
+        let policy = request.retry.clone();
+        Either::B(response.then(move |result| match result {
+            Ok(response) => Either::A(future::ok(Loop::Break(response))),
+            Err(ref e) if retry < policy.max_retries && e.is_retryable() => {
+                let backoff = policy.backoff(retry);
+                warn!("Http request failed: {}. Retrying in {:?}.", e, backoff);
+                Either::B(
+                    Delay::new(Instant::now() + backoff)
+                        .map_err(|_| Error::Cancelled)
+                        .map(move |_| Loop::Continue(retry + 1)),
+                )
+            }
+            Err(e) => Either::A(future::err(e)),
+        }))
+    }))
+}
+
+/// Sends the result to the main thread, unless the request was cancelled.
+fn deliver<T, F>(
+    callbacks: &Sender<WorldCallback>,
+    id: RequestId,
+    slot: Arc<CallbackSlot<F>>,
+    result: Result<T, Error>,
+) where
+    T: Send + 'static,
+    F: FnOnce(RequestId, Result<T, Error>, &mut World) + Send + 'static,
+{
+    if slot.is_cancelled() {
+        return;
+    }
+    let callback: WorldCallback = Box::new(move |world| {
+        // Checked again here, the request may have been cancelled while in the queue.
+        if let Some(on_done) = slot.take() {
+            on_done(id, result, world);
+        }
+    });
+    if callbacks.send(callback).is_err() {
+        error!("Failed to send http response callback: the callback queue is gone.");
+    }
+}
+
+/// Keeps the auth token of the `HttpClientResource` in sync with the `Auth` resource.
+/// The token is only injected once it has been validated.
+/// `Auth` can be inserted as is, or wrapped in `Dirty` as done by the `AutoSaveSystem`.
+/// When both are present, the `Dirty<Auth>` is used.
+pub struct HttpAuthSystem;
+
+impl<'a> System<'a> for HttpAuthSystem {
+    type SystemData = (
+        Option<Read<'a, Dirty<Auth>>>,
+        Option<Read<'a, Auth>>,
+        Option<Write<'a, HttpClientResource>>,
+    );
+
+    fn run(&mut self, (dirty_auth, plain_auth, http): Self::SystemData) {
+        let auth = match (&dirty_auth, &plain_auth) {
+            (Some(auth), _) => Some(auth.read()),
+            (None, Some(auth)) => Some(&**auth),
+            (None, None) => None,
+        };
+        if let (Some(auth), Some(mut http)) = (auth, http) {
+            let token = if auth.valid() {
+                Some(auth.token.clone())
+            } else {
+                None
+            };
+            if http.auth_token() != token.as_ref() {
+                http.set_auth_token(token);
+            }
+        }
+    }
+}
+
+/// A snapshot of the state of a download.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct DownloadStatus {
+    /// The number of bytes received so far.
+    pub received: u64,
+    /// The size of the body, if the server sent a content-length.
+    pub total: Option<u64>,
+    /// The download completed, either successfully or with an error.
+    pub finished: bool,
+}
+
+/// Progress of a download, updated from the http runtime as chunks arrive.
+/// Cheap to clone, all clones share the same state.
+#[derive(Debug, Clone, Default)]
+pub struct DownloadProgress {
+    status: Arc<Mutex<DownloadStatus>>,
+}
+
+impl DownloadProgress {
+    pub fn status(&self) -> DownloadStatus {
+        *self.lock()
+    }
+
+    /// The fraction of the body received, between 0.0 and 1.0.
+    /// None if the size of the body is unknown.
+    pub fn fraction(&self) -> Option<f32> {
+        let status = self.status();
+        match status.total {
+            Some(0) => Some(1.0),
+            Some(total) => Some(status.received as f32 / total as f32),
+            None => None,
+        }
+    }
+
+    pub fn finished(&self) -> bool {
+        self.lock().finished
+    }
+
+    fn set_total(&self, total: Option<u64>) {
+        self.lock().total = total;
+    }
+
+    fn add_received(&self, bytes: u64) {
+        self.lock().received += bytes;
+    }
+
+    fn finish(&self) {
+        self.lock().finished = true;
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<DownloadStatus> {
+        self.status
+            .lock()
+            .expect("Failed to acquire mutex lock for the download progress.")
+    }
+}
+
+/// Waits for the whole body of the response and deserializes it from json.
+/// Non-success status codes are returned as `Error::Status`.
+pub fn read_json_body<T: DeserializeOwned>(
+    response: Response<Body>,
+) -> impl Future<Item = T, Error = Error> {
+    let status = response.status();
+    response
+        .into_body()
+        .concat2()
+        .map_err(Error::Transport)
+        .and_then(move |body| {
+            if !status.is_success() {
+                return Err(Error::Status(status));
+            }
+            serde_json::from_slice::<T>(&body).map_err(Error::Deserialization)
+        })
+}
+
+pub fn https_client() -> Result<Client<HttpsConnector<HttpConnector>, Body>, Error> {
+    let https = HttpsConnector::new(2).map_err(|e| Error::Tls(e.to_string()))?;
+    Ok(Client::builder().build::<_, hyper::Body>(https))
+}
+
+pub fn post_json(url: String, data: String) -> Result<Request<Body>, Error> {
+    Request::post(&url)
+        .header("Content-Type", "application/json")
+        .body(Body::from(data))
+        .map_err(|e| Error::InvalidRequest(e.to_string()))
+}
+
+pub fn post_json_typed<T: Serialize>(url: String, data: T) -> Result<Request<Body>, Error> {
+    let data = serde_json::to_string(&data).map_err(Error::Serialization)?;
+    post_json(url, data)
+}
+
+pub fn exec_http_request(
+    client: &Client<HttpsConnector<HttpConnector>, Body>,
+    request: Request<Body>,
+    future_runtime: &mut Runtime,
+    callback_queue: &CallbackQueue,
+    on_success: Box<dyn Fn(Response<Body>) -> Box<dyn FnOnce(&mut World) + Send> + Send>,
+    on_error: Box<dyn Fn(hyper::Error) -> Box<dyn FnOnce(&mut World) + Send> + Send>,
+) {
+    let send_handle1 = callback_queue.send_handle();
+    let send_handle2 = callback_queue.send_handle();
+    let future = client
+        .request(request)
+        // If all good, just tell the user...
+        .map(move |result| {
+            let callback = on_success(result);
+            if send_handle1.send(callback).is_err() {
+                error!("Failed to send Callback to CallbackQueue from future completion.");
+            }
+        })
+        // If there was an error, let the user know...
+        .map_err(move |err| {
+            let callback = on_error(err);
+            if send_handle2.send(callback).is_err() {
+                error!("Failed to send Callback to CallbackQueue from future error.");
+            }
+        });
+
+    future_runtime.spawn(future);
+}
+
+/// Warning: Blocks the thread in which it is called until the stream has been fully consumed.
+/// Avoid using with file downloads.
+/// This will only return the first parse error instead of all of them, because its easier to use that way.
+#[deprecated(note = "Blocks the calling thread. Use `HttpClientResource::download` or `read_json_body` instead.")]
+pub fn response_to_chunks(
+    response: Response<Body>,
+) -> Vec<std::result::Result<Chunk, hyper::Error>> {
+    response.into_body().wait().collect::<Vec<_>>()
+}
+
+/// Only deserializes a single chunk. Bodies sent in multiple chunks will fail to parse,
+/// prefer `read_json_body` or `HttpClientResource`.
+pub fn parse_chunk<T: DeserializeOwned>(
+    chunk: &Chunk,
+) -> std::result::Result<T, serde_json::Error> {
+    serde_json::from_slice::<T>(&chunk)
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use crate::auth::Auth;
+    use crate::error::Error;
+    use crate::http::*;
+
+    use dirty::Dirty;
+    use hyper::StatusCode;
+
+    use amethyst::ecs::RunNow;
+    use amethyst::prelude::*;
+
+    use std::io::{BufRead, BufReader, Read as IORead, Write as IOWrite};
+    use std::net::TcpListener;
+    use std::sync::mpsc::{channel, Receiver};
+    use std::thread::spawn;
+    use std::time::Duration;
+
+    /// Serves a single http response on a random loopback port.
+    /// Returns the base url of the server and a receiver yielding the raw request it got.
+    pub(crate) fn stub_server(status: &str, body: &str) -> (String, Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind stub server");
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let response = format!(
+            "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        );
+        let (tx, rx) = channel();
+        spawn(move || {
+            let (mut stream, _) = listener.accept().expect("Stub server failed to accept");
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request = String::new();
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.to_lowercase().starts_with("content-length:") {
+                    content_length = line[15..].trim().parse().unwrap();
+                }
+                request.push_str(&line);
+                if line == "\r\n" || line.is_empty() {
+                    break;
+                }
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+            request.push_str(&String::from_utf8_lossy(&body));
+            stream.write_all(response.as_bytes()).unwrap();
+            tx.send(request).unwrap();
+        });
+        (url, rx)
+    }
+
+    /// Accepts connections but never answers them.
+    fn silent_server() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind silent server");
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        spawn(move || {
+            let _streams = listener.incoming().collect::<Vec<_>>();
+        });
+        url
+    }
+
+    /// Waits for the next callback and runs it against the world.
+    pub(crate) fn run_next_callback(
+        callbacks: &crossbeam_channel::Receiver<WorldCallback>,
+        world: &mut World,
+    ) {
+        let callback = callbacks
+            .recv_timeout(Duration::from_secs(10))
+            .expect("No http callback received");
+        callback(world);
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Score {
+        value: u32,
+    }
+
+    #[test]
+    fn get_json() {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        let mut http = HttpClientResource::with_sender(tx).unwrap();
+        let (url, _) = stub_server("200 OK", "{\"value\":         42}");
+        http.get_json::<Score, _>(&url, |_, result, world| world.insert(result.unwrap()));
+
+        let mut world = World::new();
+        run_next_callback(&rx, &mut world);
+        assert_eq!(*world.read_resource::<Score>(), Score { value: 42 });
+    }
+
+    #[test]
+    fn post_json_sends_body() {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        let mut http = HttpClientResource::with_sender(tx).unwrap();
+        let (url, requests) = stub_server("200 OK", "{\"value\":2}");
+        http.post_json::<_, Score, _>(&url, &Score { value: 1 }, |_, result, world| {
+            world.insert(result.unwrap())
+        })
+        .unwrap();
+
+        let mut world = World::new();
+        run_next_callback(&rx, &mut world);
+        assert_eq!(*world.read_resource::<Score>(), Score { value: 2 });
+        assert!(requests.recv().unwrap().ends_with("{\"value\":1}"));
+    }
+
+    #[test]
+    fn download() {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        let mut http = HttpClientResource::with_sender(tx).unwrap();
+        let (url, _) = stub_server("200 OK", "0123456789");
+        let (_, progress) = http.download(&url, |_, result, world| world.insert(result.unwrap()));
+
+        let mut world = World::new();
+        run_next_callback(&rx, &mut world);
+        assert_eq!(&*world.read_resource::<Vec<u8>>(), b"0123456789");
+        assert_eq!(
+            progress.status(),
+            DownloadStatus {
+                received: 10,
+                total: Some(10),
+                finished: true,
+            }
+        );
+        assert_eq!(progress.fraction(), Some(1.0));
+    }
+
+    #[test]
+    fn error_status() {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        let mut http = HttpClientResource::with_sender(tx).unwrap();
+        let (url, _) = stub_server("404 Not Found", "{}");
+        http.get_json::<Score, _>(&url, |_, result, world| match result {
+            Err(Error::Status(s)) => world.insert(s),
+            _ => panic!("Expected a status error"),
+        });
+
+        let mut world = World::new();
+        run_next_callback(&rx, &mut world);
+        assert_eq!(*world.read_resource::<StatusCode>(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn timeout() {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        let mut http = HttpClientResource::with_sender(tx).unwrap();
+        let request = HttpRequest::get(&silent_server()).timeout(Some(Duration::from_millis(100)));
+        let handle = http.request_json::<Score, _>(request, |id, result, world| match result {
+            Err(Error::Timeout) => world.insert(id),
+            _ => panic!("Expected a timeout error"),
+        });
+
+        let mut world = World::new();
+        run_next_callback(&rx, &mut world);
+        assert_eq!(*world.read_resource::<RequestId>(), handle.id());
+    }
+
+    #[test]
+    fn cancel_drops_callback() {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        let mut http = HttpClientResource::with_sender(tx).unwrap();
+        let (url, requests) = stub_server("200 OK", "{\"value\":1}");
+        let handle = http.get_json::<Score, _>(&url, |_, result, world| {
+            world.insert(result.unwrap())
+        });
+        handle.cancel();
+        requests.recv().unwrap();
+
+        // The callback is either never sent or does nothing.
+        let mut world = World::new();
+        if let Ok(callback) = rx.recv_timeout(Duration::from_millis(500)) {
+            callback(&mut world);
+        }
+        assert!(handle.is_cancelled());
+        assert!(world.try_fetch::<Score>().is_none());
+    }
+
+    #[test]
+    fn request_ids_are_unique() {
+        let (tx, _rx) = crossbeam_channel::unbounded();
+        let mut http = HttpClientResource::with_sender(tx).unwrap();
+        let url = silent_server();
+        let first = http.get_json::<Score, _>(&url, |_, _, _| {});
+        let second = http.get_json::<Score, _>(&url, |_, _, _| {});
+        assert_ne!(first.id(), second.id());
+    }
+
+    #[test]
+    fn exponential_backoff() {
+        let policy = RetryPolicy::new(5, Duration::from_millis(100), Duration::from_secs(1));
+        assert_eq!(policy.backoff(0), Duration::from_millis(100));
+        assert_eq!(policy.backoff(1), Duration::from_millis(200));
+        assert_eq!(policy.backoff(2), Duration::from_millis(400));
+        assert_eq!(policy.backoff(4), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn query_encoding() {
+        let request = HttpRequest::get("http://localhost/maps")
+            .query("search", "bhop & surf")
+            .query("page", "2");
+        assert_eq!(
+            request.full_url(),
+            "http://localhost/maps?search=bhop%20%26%20surf&page=2"
+        );
+        let request = HttpRequest::get("http://localhost/maps?sort=asc").query("é", "~a.b_c-d");
+        assert_eq!(
+            request.full_url(),
+            "http://localhost/maps?sort=asc&%C3%A9=~a.b_c-d"
+        );
+    }
+
+    #[test]
+    fn auth_token_injection() {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        let mut http = HttpClientResource::with_sender(tx).unwrap();
+        http.set_auth_token(Some("secret".to_string()));
+        let (url, requests) = stub_server("204 No Content", "");
+        http.send(HttpRequest::delete(&url), |_, result, world| {
+            world.insert(result.unwrap())
+        });
+
+        let mut world = World::new();
+        run_next_callback(&rx, &mut world);
+        let request = requests.recv().unwrap();
+        assert!(request.starts_with("DELETE / HTTP/1.1"));
+        assert!(request.to_lowercase().contains("authorization: bearer secret"));
+        assert!(world.read_resource::<Vec<u8>>().is_empty());
+    }
+
+    #[test]
+    fn auth_system_injects_validated_token() {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        let mut world = World::new();
+        world.insert(HttpClientResource::with_sender(tx).unwrap());
+        let mut auth = Auth::default();
+        auth.token = "secret".to_string();
+        world.insert(auth);
+
+        // Not validated yet.
+        HttpAuthSystem.run_now(&world);
+        assert_eq!(world.read_resource::<HttpClientResource>().auth_token(), None);
+
+        world.write_resource::<Auth>().set_validated(true);
+        HttpAuthSystem.run_now(&world);
+        let (url, requests) = stub_server("204 No Content", "");
+        world
+            .write_resource::<HttpClientResource>()
+            .send(HttpRequest::get(&url), |_, result, world| {
+                world.insert(result.unwrap())
+            });
+        run_next_callback(&rx, &mut world);
+        let request = requests.recv().unwrap();
+        assert!(request.to_lowercase().contains("authorization: bearer secret"));
+
+        // The saved `Dirty<Auth>` takes precedence.
+        world.insert(Dirty::new(Auth::default()));
+        HttpAuthSystem.run_now(&world);
+        assert_eq!(world.read_resource::<HttpClientResource>().auth_token(), None);
+    }
+}
@@ -0,0 +1,389 @@
+use crate::error::Error;
+use crate::http::{HttpClientResource, HttpRequest, RequestHandle};
+use crate::offline_queue::OfflineQueue;
+use crate::relative_timer::RelativeTimer;
+use crate::sec_to_display;
+
+use ::amethyst::ecs::*;
+use ::amethyst::ui::UiText;
+
+use std::collections::HashMap;
+
+/// A single line of a leaderboard, as sent by the server.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, new)]
+pub struct LeaderboardEntry {
+    /// 1 is the best.
+    pub rank: u32,
+    pub player: String,
+    /// Time in seconds or score, depending on the game.
+    pub score: f64,
+}
+
+/// The body of a score submission.
+/// The player is identified by the `Auth` token sent with the request.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, new)]
+pub struct ScoreSubmission {
+    pub map_id: String,
+    pub score: f64,
+}
+
+/// The last results received for a map.
+#[derive(Debug, Clone, Default)]
+pub struct LeaderboardCache {
+    pub top: Vec<LeaderboardEntry>,
+    pub around_me: Vec<LeaderboardEntry>,
+    /// Our own entry, as returned by the server after a submission.
+    pub personal: Option<LeaderboardEntry>,
+    /// The error of the last failed request for this map.
+    pub last_error: Option<String>,
+}
+
+/// Client and cache for a leaderboard server.
+///
+/// Expected endpoints, relative to `base_url`:
+/// * `GET maps/{map_id}/top?count=N` -> `[LeaderboardEntry]`
+/// * `GET maps/{map_id}/around?count=N` -> `[LeaderboardEntry]`, centered on the authenticated player.
+/// * `POST maps/{map_id}/scores` with a `ScoreSubmission` -> `LeaderboardEntry`
+///
+/// Requests are authenticated by the `HttpClientResource` once the `Auth` token is valid.
+/// Results are stored in this resource when they arrive.
+///
+/// Usage:
+/// ```rs
+/// world.insert(Leaderboard::new("https://example.com/leaderboard"));
+///
+/// let leaderboard = world.read_resource::<Leaderboard>();
+/// let mut http = world.write_resource::<HttpClientResource>();
+/// leaderboard.submit_time(&mut http, "map1", &world.read_resource::<RelativeTimer>())?;
+/// leaderboard.fetch_top(&mut http, "map1", 10);
+/// ```
+pub struct Leaderboard {
+    base_url: String,
+    maps: HashMap<String, LeaderboardCache>,
+}
+
+impl Leaderboard {
+    pub fn new(base_url: &str) -> Self {
+        Leaderboard {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            maps: HashMap::new(),
+        }
+    }
+
+    /// The cached results for the map, if any request completed for it.
+    pub fn get(&self, map_id: &str) -> Option<&LeaderboardCache> {
+        self.maps.get(map_id)
+    }
+
+    /// Submits the score right away. Lost if the player is offline, see `queue_score`.
+    pub fn submit_score(
+        &self,
+        http: &mut HttpClientResource,
+        map_id: &str,
+        score: f64,
+    ) -> Result<RequestHandle, Error> {
+        let map = map_id.to_string();
+        Ok(http.request_json::<LeaderboardEntry, _>(
+            self.submission_request(map_id, score)?,
+            move |_, result, world| {
+                let mut leaderboard = match world.try_fetch_mut::<Leaderboard>() {
+                    Some(leaderboard) => leaderboard,
+                    None => {
+                        warn!(
+                            "The Leaderboard was removed before the score for {} was submitted.",
+                            map
+                        );
+                        return;
+                    }
+                };
+                let cache = leaderboard.cache_mut(&map);
+                match result {
+                    Ok(entry) => cache.personal = Some(entry),
+                    Err(e) => {
+                        error!("Failed to submit leaderboard score for {}: {}", map, e);
+                        cache.last_error = Some(e.to_string());
+                    }
+                }
+            },
+        ))
+    }
+
+    /// Submits the duration of the timer.
+    pub fn submit_time(
+        &self,
+        http: &mut HttpClientResource,
+        map_id: &str,
+        timer: &RelativeTimer,
+    ) -> Result<RequestHandle, Error> {
+        self.submit_score(http, map_id, timer.duration())
+    }
+
+    /// Queues the score in the `OfflineQueue`, so it is delivered once the player is online.
+    /// Only the last queued score of each map is kept, so only queue scores that beat the previous ones.
+    pub fn queue_score(
+        &self,
+        queue: &mut OfflineQueue,
+        map_id: &str,
+        score: f64,
+    ) -> Result<(), Error> {
+        let request = self.submission_request(map_id, score)?;
+        queue.enqueue(&format!("leaderboard/{}", map_id), &request);
+        Ok(())
+    }
+
+    /// Fetches the `count` best entries of the map.
+    pub fn fetch_top(
+        &self,
+        http: &mut HttpClientResource,
+        map_id: &str,
+        count: u32,
+    ) -> RequestHandle {
+        self.fetch(http, map_id, "top", count, |cache, entries| {
+            cache.top = entries
+        })
+    }
+
+    /// Fetches `count` entries around the authenticated player.
+    pub fn fetch_around_me(
+        &self,
+        http: &mut HttpClientResource,
+        map_id: &str,
+        count: u32,
+    ) -> RequestHandle {
+        self.fetch(http, map_id, "around", count, |cache, entries| {
+            cache.around_me = entries
+        })
+    }
+
+    fn fetch<F>(
+        &self,
+        http: &mut HttpClientResource,
+        map_id: &str,
+        endpoint: &str,
+        count: u32,
+        store: F,
+    ) -> RequestHandle
+    where
+        F: FnOnce(&mut LeaderboardCache, Vec<LeaderboardEntry>) + Send + 'static,
+    {
+        let request = HttpRequest::get(&self.map_url(map_id, endpoint))
+            .query("count", &count.to_string())
+            .header("Accept", "application/json");
+        let map = map_id.to_string();
+        let endpoint = endpoint.to_string();
+        http.request_json::<Vec<LeaderboardEntry>, _>(request, move |_, result, world| {
+            let mut leaderboard = match world.try_fetch_mut::<Leaderboard>() {
+                Some(leaderboard) => leaderboard,
+                None => {
+                    warn!(
+                        "The Leaderboard was removed before {} for {} was fetched.",
+                        endpoint, map
+                    );
+                    return;
+                }
+            };
+            let cache = leaderboard.cache_mut(&map);
+            match result {
+                Ok(entries) => {
+                    cache.last_error = None;
+                    store(cache, entries);
+                }
+                Err(e) => {
+                    error!(
+                        "Failed to fetch leaderboard {} for {}: {}",
+                        endpoint, map, e
+                    );
+                    cache.last_error = Some(e.to_string());
+                }
+            }
+        })
+    }
+
+    fn submission_request(&self, map_id: &str, score: f64) -> Result<HttpRequest, Error> {
+        HttpRequest::post_json(
+            &self.map_url(map_id, "scores"),
+            &ScoreSubmission::new(map_id.to_string(), score),
+        )
+    }
+
+    fn map_url(&self, map_id: &str, endpoint: &str) -> String {
+        format!(
+            "{}/maps/{}/{}",
+            self.base_url,
+            crate::http::url_encode(map_id),
+            endpoint
+        )
+    }
+
+    fn cache_mut(&mut self, map_id: &str) -> &mut LeaderboardCache {
+        self.maps
+            .entry(map_id.to_string())
+            .or_insert_with(LeaderboardCache::default)
+    }
+}
+
+/// Which list of the leaderboard to display.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LeaderboardList {
+    Top,
+    AroundMe,
+}
+
+/// Add this next to an `UiText` to display a leaderboard in it, one entry per line.
+/// Updated by the `UiLeaderboardSystem`.
+#[derive(Debug, Clone, Serialize, Deserialize, new)]
+pub struct UiLeaderboardText {
+    pub map_id: String,
+    pub list: LeaderboardList,
+    /// The number of decimals displayed for the scores.
+    #[new(value = "3")]
+    pub decimals: usize,
+}
+
+impl UiLeaderboardText {
+    pub fn get_text(&self, leaderboard: &Leaderboard) -> String {
+        let cache = match leaderboard.get(&self.map_id) {
+            Some(c) => c,
+            None => return String::new(),
+        };
+        let entries = match self.list {
+            LeaderboardList::Top => &cache.top,
+            LeaderboardList::AroundMe => &cache.around_me,
+        };
+        entries
+            .iter()
+            .map(|e| {
+                format!(
+                    "{}. {} {}",
+                    e.rank,
+                    e.player,
+                    sec_to_display(e.score, self.decimals)
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+impl Component for UiLeaderboardText {
+    type Storage = DenseVecStorage<Self>;
+}
+
+/// Updates the `UiText` of the entities having an `UiLeaderboardText`, the same way `UiAutoTextSystem` does.
+pub struct UiLeaderboardSystem;
+
+impl<'a> System<'a> for UiLeaderboardSystem {
+    type SystemData = (
+        Option<Read<'a, Leaderboard>>,
+        ReadStorage<'a, UiLeaderboardText>,
+        WriteStorage<'a, UiText>,
+    );
+
+    fn run(&mut self, (leaderboard, autotexts, mut texts): Self::SystemData) {
+        if let Some(leaderboard) = leaderboard {
+            for (autotext, text) in (&autotexts, &mut texts).join() {
+                let new_text = autotext.get_text(&leaderboard);
+                if text.text != new_text {
+                    text.text = new_text;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::http::test::{run_next_callback, stub_server};
+    use crate::http::HttpClientResource;
+    use crate::leaderboard::*;
+
+    use amethyst::prelude::*;
+
+    fn setup(
+        base_url: &str,
+    ) -> (
+        HttpClientResource,
+        crossbeam_channel::Receiver<crate::http::WorldCallback>,
+        World,
+    ) {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        let http = HttpClientResource::with_sender(tx).unwrap();
+        let mut world = World::new();
+        world.insert(Leaderboard::new(base_url));
+        (http, rx, world)
+    }
+
+    #[test]
+    fn fetch_top() {
+        let (url, requests) = stub_server(
+            "200 OK",
+            r#"[{"rank":1,"player":"jojo","score":12.5},{"rank":2,"player":"anne","score":13.25}]"#,
+        );
+        let (mut http, rx, mut world) = setup(&format!("{}/", url));
+        world
+            .read_resource::<Leaderboard>()
+            .fetch_top(&mut http, "map 1", 2);
+        run_next_callback(&rx, &mut world);
+
+        assert!(requests
+            .recv()
+            .unwrap()
+            .starts_with("GET /maps/map%201/top?count=2 HTTP/1.1"));
+        let leaderboard = world.read_resource::<Leaderboard>();
+        let cache = leaderboard.get("map 1").unwrap();
+        assert_eq!(cache.top.len(), 2);
+        assert_eq!(
+            cache.top[1],
+            LeaderboardEntry::new(2, "anne".to_string(), 13.25)
+        );
+        assert_eq!(
+            UiLeaderboardText::new("map 1".to_string(), LeaderboardList::Top)
+                .get_text(&leaderboard),
+            "1. jojo 12.500\n2. anne 13.250"
+        );
+    }
+
+    #[test]
+    fn submit_time() {
+        let (url, requests) = stub_server("200 OK", r#"{"rank":7,"player":"jojo","score":20.0}"#);
+        let (mut http, rx, mut world) = setup(&url);
+        http.set_auth_token(Some("token".to_string()));
+        let mut timer = RelativeTimer::default();
+        timer.start(10.0);
+        timer.update(30.0);
+        world
+            .read_resource::<Leaderboard>()
+            .submit_time(&mut http, "map1", &timer)
+            .unwrap();
+        run_next_callback(&rx, &mut world);
+
+        let request = requests.recv().unwrap();
+        assert!(request.starts_with("POST /maps/map1/scores HTTP/1.1"));
+        assert!(request
+            .to_lowercase()
+            .contains("authorization: bearer token"));
+        assert!(request.ends_with(r#"{"map_id":"map1","score":20.0}"#));
+        assert_eq!(
+            world
+                .read_resource::<Leaderboard>()
+                .get("map1")
+                .unwrap()
+                .personal,
+            Some(LeaderboardEntry::new(7, "jojo".to_string(), 20.0))
+        );
+    }
+
+    #[test]
+    fn fetch_error_is_cached() {
+        let (url, _) = stub_server("500 Internal Server Error", "{}");
+        let (mut http, rx, mut world) = setup(&url);
+        world
+            .read_resource::<Leaderboard>()
+            .fetch_around_me(&mut http, "map1", 5);
+        run_next_callback(&rx, &mut world);
+
+        let leaderboard = world.read_resource::<Leaderboard>();
+        assert!(leaderboard.get("map1").unwrap().last_error.is_some());
+        assert!(leaderboard.get("map1").unwrap().around_me.is_empty());
+    }
+}
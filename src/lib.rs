@@ -1,4 +1,5 @@
 extern crate amethyst;
+extern crate crossbeam_channel;
 #[macro_use]
 extern crate serde;
 extern crate ron;
@@ -28,9 +29,13 @@ mod auto_save;
 mod auto_text;
 #[cfg(feature = "discord")]
 mod discord;
+mod error;
 mod follow_mouse;
+mod http;
+mod leaderboard;
 mod movement;
 mod noclip;
+mod offline_queue;
 mod relative_timer;
 mod time_driver;
 mod terminal;
@@ -43,9 +48,13 @@ pub use self::auto_save::*;
 pub use self::auto_text::*;
 #[cfg(feature = "discord")]
 pub use self::discord::*;
+pub use self::error::*;
 pub use self::follow_mouse::*;
+pub use self::http::*;
+pub use self::leaderboard::*;
 pub use self::movement::*;
 pub use self::noclip::*;
+pub use self::offline_queue::*;
 pub use self::relative_timer::*;
 pub use self::time_driver::*;
 pub use self::terminal::*;
@@ -61,19 +70,9 @@ use ::amethyst::prelude::*;
 
 use ::amethyst::utils::removal::Removal;
 
-use hyper::client::HttpConnector;
-use hyper::{Body, Chunk, Client, Request, Response};
-use hyper_tls::HttpsConnector;
-
-use serde::de::DeserializeOwned;
-use serde::Serialize;
-
 use std::fmt::Debug;
 use std::ops::{Add, Sub};
 
-use tokio::prelude::{Future, Stream};
-use tokio::runtime::Runtime;
-
 //use nphysics::{World, Body3d};
 
 /*pub trait AssetToFormat<T> where T: Sized{
@@ -130,86 +129,6 @@ impl Component for NavigationButton{
     type Storage = VecStorage<Self>;
 }*/
 
-pub fn https_client() -> Client<HttpsConnector<HttpConnector>, Body> {
-    let https = HttpsConnector::new(2).expect("TLS initialization failed");
-    Client::builder().build::<_, hyper::Body>(https)
-}
-
-pub fn post_json(url: String, data: String) -> Request<Body> {
-    Request::post(&url)
-        .header("Content-Type", "application/json")
-        .body(Body::from(data))
-        .unwrap()
-}
-
-pub fn post_json_typed<T: Serialize>(url: String, data: T) -> Request<Body> {
-    Request::post(&url)
-        .header("Content-Type", "application/json")
-        .body(Body::from(serde_json::to_string(&data).expect(
-            "Failed to serialize data to json for post request creation.",
-        )))
-        .expect("Failed to create post `Request`")
-}
-
-pub fn exec_http_request(
-    client: &Client<HttpsConnector<HttpConnector>, Body>,
-    request: Request<Body>,
-    future_runtime: &mut Runtime,
-    callback_queue: &CallbackQueue,
-    on_success: Box<dyn Fn(Response<Body>) -> Box<dyn FnOnce(&mut World) + Send> + Send>,
-    on_error: Box<dyn Fn(hyper::Error) -> Box<dyn FnOnce(&mut World) + Send> + Send>,
-) {
-    let send_handle1 = callback_queue.send_handle();
-    let send_handle2 = callback_queue.send_handle();
-    let future = client
-        .request(request)
-        /*.and_then(move |result| {
-            println!("Response: {}", result.status());
-            println!("Headers: {:#?}", result.headers());
-
-            // The body is a stream, and for_each returns a new Future
-            // when the stream is finished, and calls the closure on
-            // each chunk of the body...
-            result.into_body().for_each(move |chunk| {
-                /*io::stdout().write_all(&chunk)
-                    .map_err(|e| panic!("example expects stdout is open, error={}", e))*/
-                match serde_json::from_slice::<Auth>(&chunk) {
-                    Ok(a) => {},
-                    Err(e) => eprintln!("Failed to parse received data to Auth: {}", e),
-                }
-                Ok(())
-            })
-            //serde_json::from_slice::<Auth>(result.into_body())
-        })*/
-        // If all good, just tell the user...
-        .map(move |result| {
-            let callback = on_success(result);
-            send_handle1.send(callback).expect("Failed to send Callback to CallbackQueue from future completion.");
-        })
-        // If there was an error, let the user know...
-        .map_err(move |err| {
-            let callback = on_error(err);
-            send_handle2.send(callback).expect("Failed to send Callback to CallbackQueue from future error.");
-        });
-
-    future_runtime.spawn(future);
-}
-
-/// Warning: Blocks the thread in which it is called until the stream has been fully consumed.
-/// Avoid using with file downloads.
-/// This will only return the first parse error instead of all of them, because its easier to use that way.
-pub fn response_to_chunks(
-    response: Response<Body>,
-) -> Vec<std::result::Result<Chunk, hyper::Error>> {
-    response.into_body().wait().collect::<Vec<_>>()
-}
-
-pub fn parse_chunk<T: DeserializeOwned>(
-    chunk: &Chunk,
-) -> std::result::Result<T, serde_json::Error> {
-    serde_json::from_slice::<T>(&chunk)
-}
-
 pub fn sec_to_display(secs: f64, decimals: usize) -> String {
     if secs > -0.00001 && secs < 0.00001 {
         String::from("-")
@@ -1,29 +1,115 @@
+use ::amethyst::core::math::{Point3, Vector3};
 use ::amethyst::core::timing::Time;
 use ::amethyst::core::*;
+use ::amethyst::ecs::storage::MaskedStorage;
 use ::amethyst::ecs::*;
+use ::amethyst::shrev::EventChannel;
 
 use serde::Serialize;
 
-use nphysics_ecs::ncollide::query::*;
+use nphysics_ecs::ncollide::pipeline::CollisionGroups;
+use nphysics_ecs::ncollide::query::{Ray, RayIntersection};
 use nphysics_ecs::*;
 use specs_physics::colliders::ColliderComponent;
 
-#[derive(Debug, Clone, Default, Serialize, Deserialize, new)]
+use std::f32::consts::FRAC_PI_4;
+use std::ops::Deref;
+
+/// Tells if an entity is standing on the ground. Updated by the `GroundCheckerSystem`.
+#[derive(Debug, Clone, Serialize, Deserialize, new)]
 pub struct Grounded {
+    /// On walkable ground. False while sliding.
     #[new(value = "false")]
     pub ground: bool,
+    /// The time at which the entity landed.
     #[new(default)]
     pub since: f64,
+    /// The time at which the entity last left the ground. None if it never left it.
+    #[new(default)]
+    #[serde(skip)]
+    pub left_ground: Option<f64>,
+    /// The maximum distance between the origin of the entity and the ground.
+    /// Usually a bit more than the distance between the center and the bottom of its collider.
     pub distance_check: f32,
-    /// Checks if the selected entity collides with the ground.
+    /// Checks the ground below the selected entity instead, for example the feet of the player.
     #[serde(skip)]
     pub watch_entity: Option<Entity>,
+    /// The steepest slope considered as ground, in radians.
+    /// The entity slides on steeper slopes, like surf ramps.
+    #[new(value = "FRAC_PI_4")]
+    #[serde(default = "default_max_slope")]
+    pub max_slope: f32,
+    /// Touching a slope steeper than `max_slope`.
+    #[new(default)]
+    #[serde(skip)]
+    pub sliding: bool,
+    /// The entity below, if it is in range. Fetch its components to know the ground type or material.
+    #[new(default)]
+    #[serde(skip)]
+    pub ground_entity: Option<Entity>,
+    /// The normal of the surface below, if it is in range.
+    #[new(default)]
+    #[serde(skip)]
+    pub normal: Option<Vector3<f32>>,
+    /// The angle between the surface below and the horizontal, in radians. 0 when nothing is in range.
+    #[new(default)]
+    #[serde(skip)]
+    pub slope: f32,
+    /// The ground state during the last check. Unlike `ground`, it isn't changed by jumping.
+    #[new(default)]
+    #[serde(skip)]
+    was_ground: bool,
+}
+
+fn default_max_slope() -> f32 {
+    FRAC_PI_4
+}
+
+impl Default for Grounded {
+    fn default() -> Self {
+        Grounded::new(0.0, None)
+    }
+}
+
+impl Grounded {
+    /// Touching something below, walkable or not.
+    pub fn touching(&self) -> bool {
+        self.ground || self.sliding
+    }
+
+    /// The component of the ground entity, for example its ObjectType.
+    pub fn ground_component<'a, T, D>(&self, storage: &'a Storage<T, D>) -> Option<&'a T>
+    where
+        T: Component,
+        D: Deref<Target = MaskedStorage<T>>,
+    {
+        self.ground_entity.and_then(|e| storage.get(e))
+    }
 }
 
 impl Component for Grounded {
     type Storage = DenseVecStorage<Self>;
 }
 
+/// Sent by the `GroundCheckerSystem` in the `EventChannel<GroundEvent>`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GroundEvent {
+    /// The entity touched walkable ground.
+    Landed {
+        entity: Entity,
+        /// The downward speed of the entity when it landed. 0 if it has no rigid body.
+        impact_velocity: f32,
+        /// The time spent in the air, in seconds. 0 if the entity never left the ground.
+        airtime: f64,
+    },
+    /// The entity walked off a ledge, jumped or started sliding.
+    LeftGround { entity: Entity },
+}
+
+/// An entity moving up faster than this doesn't land, for example right after jumping.
+const LANDING_MAX_UP_VELOCITY: f32 = 0.1;
+
+/// Marks an entity as ground, regardless of its collider type.
 #[derive(Default, new)]
 pub struct GroundCheckTag;
 
@@ -31,12 +117,52 @@ impl Component for GroundCheckTag {
     type Storage = DenseVecStorage<Self>;
 }
 
+/// Casts a ray down from the entities having a `Grounded` component,
+/// through the colliders of the physics world.
+/// The entity is grounded if the ray hits a collider closer than `Grounded::distance_check`,
+/// and that collider either has a `T` component contained in `collider_types` or a `GroundCheckTag`.
+/// If the slope of the closest hit is steeper than `Grounded::max_slope`, the entity is sliding instead.
+///
+/// Sends a `GroundEvent` when an entity lands or leaves the ground.
+///
 /// T: ObjectType for collider checks
 #[derive(new)]
 pub struct GroundCheckerSystem<T> {
     pub collider_types: Vec<T>,
-    //#[new(default)]
-    //contact_reader: Option<ReaderId<EntityProximityEvent>>,
+}
+
+impl<T: Component + PartialEq> GroundCheckerSystem<T> {
+    /// If the entity can be stood on.
+    fn is_ground<D1, D2>(
+        &self,
+        entity: Entity,
+        object_types: &Storage<T, D1>,
+        ground_checks: &Storage<GroundCheckTag, D2>,
+    ) -> bool
+    where
+        D1: Deref<Target = MaskedStorage<T>>,
+        D2: Deref<Target = MaskedStorage<GroundCheckTag>>,
+    {
+        ground_checks.contains(entity)
+            || object_types
+                .get(entity)
+                .map(|t| self.collider_types.contains(t))
+                .unwrap_or(false)
+    }
+}
+
+/// The closest ray hit within `distance_check` that counts as ground.
+fn closest_ground(
+    hits: impl Iterator<Item = (Entity, RayIntersection<f32>)>,
+    distance_check: f32,
+    is_ground: impl Fn(Entity) -> bool,
+) -> Option<(Entity, RayIntersection<f32>)> {
+    hits.filter(|(hit, intersection)| intersection.toi <= distance_check && is_ground(*hit))
+        .min_by(|(_, a), (_, b)| {
+            a.toi
+                .partial_cmp(&b.toi)
+                .unwrap_or(std::cmp::Ordering::Equal)
+        })
 }
 
 impl<'a, T: Component + PartialEq> System<'a> for GroundCheckerSystem<T> {
@@ -49,6 +175,8 @@ impl<'a, T: Component + PartialEq> System<'a> for GroundCheckerSystem<T> {
         ReadExpect<'a, GeometricalWorldRes<f32>>,
         ReadStorage<'a, ColliderComponent<f32>>,
         ReadStorage<'a, GroundCheckTag>,
+        ReadRigidBodies<'a, f32>,
+        Write<'a, EventChannel<GroundEvent>>,
     );
 
     fn run(
@@ -57,122 +185,268 @@ impl<'a, T: Component + PartialEq> System<'a> for GroundCheckerSystem<T> {
             entities,
             transforms,
             mut grounded,
-            _objecttypes,
+            object_types,
             time,
-            _contacts,
+            geometrical_world,
             colliders,
             ground_checks,
+            rigid_bodies,
+            mut events,
         ): Self::SystemData,
     ) {
-        //let down = -Vector3::<f32>::y();
-        for (_entity, _transform2, _player_collider, mut grounded) in
-            (&*entities, &transforms, &colliders, &mut grounded).join()
-        {
-            //let mut ground = false;
-
-            /*let ray = Ray3::new(Point3::from_vec(transform.translation), down);
-
-            // For all in ray
-            for (v, p) in query_ray(&*tree, ray) {
-                // Not self and close enough
-                if v.value != entity
-                    && (transform.translation - Vector3::new(p.x, p.y, p.z)).magnitude()
-                        <= grounded.distance_check
-                {
-                    // If we can jump off that type of collider
-                    if let Some(obj_type) = objecttypes.get(v.value) {
-                        if self.collider_types.contains(obj_type) {
-                            ground = true;
-                        }
-                    }
-                    //info!("hit bounding volume of {:?} at point {:?}", v.value, p);
-                }
-            }*/
-
-            /*info!("run {:?}", entity);
-            // Check for secondary collider if any
-            for contact in contacts.read(&mut self.contact_reader.as_mut().unwrap()) {
-                info!("Contact {:?} -> {:?}",contact.0, contact.1);
-                // Here because we need to empty the contacts EventChannel
-                if let Some(secondary) = grounded.watch_entity {
-                    info!("Secondary");
-                    if contact.0 == entity || contact.1 == entity {
-                        // We hit our player... let's ignore that.
-                        continue;
-                    }
-                    info!("tmp1");
-                    if contact.0 != secondary && contact.1 != secondary {
-                        // This has nothing to do with the secondary collider. Skip!
-                        continue;
-                    }
-                    info!("type check");
-                    let type1 = objecttypes.get(contact.0);
-                    let type2 = objecttypes.get(contact.1);
-
-                    if type1.is_none() || type2.is_none() {
-                        continue;
-                    }
-                    info!("good to go");
-                    // If we can jump off that type of collider
-                    if self.collider_types.contains(type1.unwrap())
-                        || self.collider_types.contains(type2.unwrap())
-                    {
-                        match contact.2.new_status {
-                            // Collision with ground
-                            Proximity::Intersecting | Proximity::WithinMargin => {
-                                if ground && !grounded.ground {
-                                    // Just grounded
-                                    grounded.since = time.absolute_time_seconds();
-                                }
-                                grounded.ground = true;
-                            },
-                            // Collision stop
-                            Proximity::Disjoint => {
-                                grounded.ground = false;
-                            }
-                        }
-                        ground = true;
-                    }
+        let groups = CollisionGroups::new();
+        for (entity, mut grounded) in (&*entities, &mut grounded).join() {
+            let origin_entity = grounded.watch_entity.unwrap_or(entity);
+            // The global position, in case the entity has a parent.
+            let origin = match transforms.get(origin_entity) {
+                Some(transform) => {
+                    let matrix = transform.global_matrix();
+                    Point3::new(matrix[(0, 3)], matrix[(1, 3)], matrix[(2, 3)])
                 }
+                None => continue,
+            };
+            let ray = Ray::new(origin, -Vector3::<f32>::y());
 
+            let hit = closest_ground(
+                geometrical_world
+                    .interferences_with_ray(&colliders, &ray, &groups)
+                    .map(|(hit, _, intersection)| (hit, intersection)),
+                grounded.distance_check,
+                |hit| {
+                    hit != entity
+                        && hit != origin_entity
+                        && self.is_ground(hit, &object_types, &ground_checks)
+                },
+            );
 
-            }*/
-
-            if let Some(secondary) = grounded.watch_entity {
-                let transform = transforms
-                    .get(secondary)
-                    .expect("No transform component on secondary collider.");
-                let feet_collider = colliders
-                    .get(secondary)
-                    .expect("No collider component on secondary collider.");
-                info!(
-                    "Gonna check for collision at player pos {:?}",
-                    transform.translation()
-                );
-
-                let ground = (&*entities, &transforms, &colliders, &ground_checks)
-                    .join()
-                    .any(|(_entity, tr, collider, _)| {
-                        if let Proximity::Intersecting = proximity(
-                            &transform.isometry(),
-                            &*feet_collider.shape(),
-                            &tr.isometry(),
-                            &*collider.shape(),
-                            0.0,
-                        ) {
-                            warn!("COLLISION!!!");
-                            true
-                        } else {
-                            false
-                        }
-                    });
-
-                if ground && !grounded.ground {
-                    // Just grounded
-                    grounded.since = time.absolute_time_seconds();
-                }
-                grounded.ground = ground;
+            let vertical_velocity = rigid_bodies
+                .get(entity)
+                .map(|rb| rb.velocity().linear.y)
+                .unwrap_or(0.0);
+            if let Some(event) = apply_hit(
+                entity,
+                &mut grounded,
+                hit,
+                vertical_velocity,
+                time.absolute_time_seconds(),
+            ) {
+                events.single_write(event);
             }
         }
     }
 }
+
+/// Updates the `Grounded` component of the entity from the ground hit below it.
+/// Returns the event to send if the entity landed or left the ground.
+fn apply_hit(
+    entity: Entity,
+    grounded: &mut Grounded,
+    hit: Option<(Entity, RayIntersection<f32>)>,
+    vertical_velocity: f32,
+    now: f64,
+) -> Option<GroundEvent> {
+    let was_grounded = grounded.was_ground;
+
+    let ground = if let Some((hit, intersection)) = hit {
+        let slope = intersection.normal.angle(&Vector3::y());
+        grounded.ground_entity = Some(hit);
+        grounded.normal = Some(intersection.normal);
+        grounded.slope = slope;
+        grounded.sliding = slope > grounded.max_slope;
+        !grounded.sliding && (grounded.ground || vertical_velocity <= LANDING_MAX_UP_VELOCITY)
+    } else {
+        grounded.ground_entity = None;
+        grounded.normal = None;
+        grounded.slope = 0.0;
+        grounded.sliding = false;
+        false
+    };
+    grounded.ground = ground;
+    grounded.was_ground = ground;
+
+    if ground && !was_grounded {
+        // Just grounded
+        grounded.since = now;
+        Some(GroundEvent::Landed {
+            entity,
+            impact_velocity: (-vertical_velocity).max(0.0),
+            airtime: grounded.left_ground.map(|t| now - t).unwrap_or(0.0),
+        })
+    } else if !ground && was_grounded {
+        grounded.left_ground = Some(now);
+        Some(GroundEvent::LeftGround { entity })
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::movement::ground::*;
+
+    use crate::movement::Jump;
+
+    use nphysics_ecs::ncollide::shape::FeatureId;
+
+    use std::time::Duration;
+
+    #[derive(PartialEq)]
+    enum TestType {
+        Floor,
+        Wall,
+    }
+
+    impl Component for TestType {
+        type Storage = VecStorage<Self>;
+    }
+
+    fn intersection(toi: f32) -> RayIntersection<f32> {
+        RayIntersection::new(toi, Vector3::y(), FeatureId::Unknown)
+    }
+
+    #[test]
+    fn land_and_leave() {
+        let mut world = World::new();
+        let entity = world.create_entity().build();
+        let floor = world.create_entity().build();
+        let floor_hit = Some((floor, intersection(0.5)));
+        let mut grounded = Grounded::new(1.0, None);
+
+        // Moving up too fast to land.
+        assert_eq!(apply_hit(entity, &mut grounded, floor_hit, 2.0, 1.0), None);
+        assert!(!grounded.ground);
+        assert_eq!(grounded.ground_entity, Some(floor));
+        assert_eq!(
+            apply_hit(entity, &mut grounded, floor_hit, -3.0, 1.5),
+            Some(GroundEvent::Landed {
+                entity,
+                impact_velocity: 3.0,
+                airtime: 0.0,
+            })
+        );
+        assert!(grounded.ground);
+        assert_eq!(grounded.since, 1.5);
+        // Staying on the ground, even when pushed up.
+        assert_eq!(apply_hit(entity, &mut grounded, floor_hit, 2.0, 2.0), None);
+        assert!(grounded.ground);
+
+        assert_eq!(
+            apply_hit(entity, &mut grounded, None, 0.0, 3.0),
+            Some(GroundEvent::LeftGround { entity })
+        );
+        assert!(!grounded.ground);
+        assert_eq!(grounded.ground_entity, None);
+        assert_eq!(grounded.left_ground, Some(3.0));
+        assert_eq!(
+            apply_hit(entity, &mut grounded, floor_hit, -1.0, 3.5),
+            Some(GroundEvent::Landed {
+                entity,
+                impact_velocity: 1.0,
+                airtime: 0.5,
+            })
+        );
+    }
+
+    #[test]
+    fn leave_ground_when_jumping() {
+        let mut world = World::new();
+        let entity = world.create_entity().build();
+        let floor = world.create_entity().build();
+        let floor_hit = Some((floor, intersection(0.5)));
+        let mut grounded = Grounded::new(1.0, None);
+        let mut jump = Jump::new(false, true, 5.0, false);
+        jump.jump_cooldown = 0.0;
+        jump.input_cooldown = 0.0;
+        let mut time = Time::default();
+        time.set_delta_time(Duration::from_secs(1));
+
+        let now = time.absolute_time_seconds();
+        assert!(apply_hit(entity, &mut grounded, floor_hit, 0.0, now).is_some());
+        let velocity = jump.update(true, &time, Some(&mut grounded), Vector3::zeros());
+        assert_eq!(velocity, Some(Vector3::new(0.0, 5.0, 0.0)));
+
+        // Still in range of the floor right after jumping.
+        time.set_delta_time(Duration::from_secs(1));
+        let now = time.absolute_time_seconds();
+        assert_eq!(
+            apply_hit(entity, &mut grounded, floor_hit, 5.0, now),
+            Some(GroundEvent::LeftGround { entity })
+        );
+        assert_eq!(grounded.left_ground, Some(now));
+
+        time.set_delta_time(Duration::from_secs(1));
+        assert_eq!(
+            apply_hit(
+                entity,
+                &mut grounded,
+                floor_hit,
+                -5.0,
+                time.absolute_time_seconds()
+            ),
+            Some(GroundEvent::Landed {
+                entity,
+                impact_velocity: 5.0,
+                airtime: 1.0,
+            })
+        );
+    }
+
+    #[test]
+    fn slide_on_steep_slopes() {
+        let mut world = World::new();
+        let entity = world.create_entity().build();
+        let ramp = world.create_entity().build();
+        let mut grounded = Grounded::new(1.0, None);
+        grounded.ground = true;
+
+        // 60 degrees.
+        let normal = Vector3::new(3.0f32.sqrt(), 1.0, 0.0).normalize();
+        let steep = RayIntersection::new(0.5, normal, FeatureId::Unknown);
+        assert_eq!(
+            apply_hit(entity, &mut grounded, Some((ramp, steep)), 0.0, 1.0),
+            Some(GroundEvent::LeftGround { entity })
+        );
+        assert!(grounded.sliding);
+        assert!(grounded.touching());
+        assert!((grounded.slope - std::f32::consts::FRAC_PI_3).abs() < 1e-3);
+    }
+
+    #[test]
+    fn closest_hit_in_range() {
+        let mut world = World::new();
+        let near = world.create_entity().build();
+        let far = world.create_entity().build();
+        let hits = vec![(far, intersection(0.8)), (near, intersection(0.5))];
+        let hit = closest_ground(hits.clone().into_iter(), 1.0, |_| true);
+        assert_eq!(hit.map(|(e, i)| (e, i.toi)), Some((near, 0.5)));
+        // Out of range.
+        assert!(closest_ground(hits.clone().into_iter(), 0.4, |_| true).is_none());
+        // The closest one isn't ground.
+        let hit = closest_ground(hits.into_iter(), 1.0, |e| e != near);
+        assert_eq!(hit.map(|(e, _)| e), Some(far));
+    }
+
+    #[test]
+    fn filter_collider_types() {
+        let mut world = World::new();
+        world.register::<TestType>();
+        world.register::<GroundCheckTag>();
+        let floor = world.create_entity().with(TestType::Floor).build();
+        let wall = world.create_entity().with(TestType::Wall).build();
+        let tagged = world
+            .create_entity()
+            .with(TestType::Wall)
+            .with(GroundCheckTag)
+            .build();
+        let untyped = world.create_entity().build();
+
+        let system = GroundCheckerSystem::new(vec![TestType::Floor]);
+        let object_types = world.read_storage::<TestType>();
+        let ground_checks = world.read_storage::<GroundCheckTag>();
+        let is_ground = |e| system.is_ground(e, &object_types, &ground_checks);
+        assert!(is_ground(floor));
+        assert!(!is_ground(wall));
+        assert!(is_ground(tagged));
+        assert!(!is_ground(untyped));
+    }
+}
@@ -13,6 +13,8 @@ use nphysics_ecs::*;
 #[derive(Default, new)]
 pub struct Jump {
     pub absolute: bool,
+    /// Only jump while on walkable ground. Requires a `Grounded` component.
+    /// Sliding on a slope steeper than `Grounded::max_slope` doesn't count.
     pub check_ground: bool,
     pub jump_force: f32,
     pub auto_jump: bool,
@@ -20,6 +22,22 @@ pub struct Jump {
     pub jump_cooldown: f64,
     #[new(value = "0.1")]
     pub input_cooldown: f64,
+    /// The time after walking off a ledge during which jumping is still allowed, in seconds.
+    #[new(value = "0.1")]
+    pub coyote_time: f64,
+    /// Pressing the jump key this long before landing still jumps when landing, in seconds.
+    #[new(value = "0.1")]
+    pub jump_buffer: f64,
+    /// Multiplies the upward velocity when the jump key is released while going up,
+    /// so that short presses make lower jumps. None always jumps at full height.
+    #[new(default)]
+    pub jump_cut: Option<f32>,
+    /// The number of jumps allowed in the air. Resets when landing.
+    /// Air jumps set the vertical velocity, even when `absolute` is false.
+    #[new(default)]
+    pub air_jumps: u32,
+    #[new(default)]
+    pub air_jumps_left: u32,
     /// Multiplier. Time can go in the negatives.
     #[new(default)]
     pub jump_timing_boost: Option<PartialFunction<f64, f32>>,
@@ -27,100 +45,352 @@ pub struct Jump {
     pub last_jump: f64,
     #[new(default)]
     pub last_jump_offset: f64,
+    /// The last time a valid jump input was considered.
+    #[new(default)]
+    pub last_logical_press: f64,
+    /// Was the jump key pressed last frame?
+    #[new(default)]
+    pub input_hold: bool,
+    /// The last time the jump key was pressed.
+    #[new(default)]
+    pub last_physical_press: f64,
+    /// The last press passed the `input_cooldown` but didn't make us jump yet.
+    #[new(default)]
+    pub buffered_press: bool,
+    /// Going up after a jump, with the jump key still held.
+    #[new(default)]
+    pub rising: bool,
+}
+
+impl Jump {
+    /// Walked off the ground less than `coyote_time` ago, without jumping since landing.
+    pub fn in_coyote_time(&self, grounded: &Grounded, now: f64) -> bool {
+        !grounded.ground
+            && !grounded.sliding
+            && grounded
+                .left_ground
+                .map(|t| now - t <= self.coyote_time)
+                .unwrap_or(false)
+            && self.last_jump < grounded.since
+    }
+
+    /// Updates the jump state for this frame.
+    /// `pressed` tells if the jump key is down, and `velocity` is the current velocity of the entity.
+    /// Returns the new velocity if it changed.
+    pub fn update(
+        &mut self,
+        pressed: bool,
+        time: &Time,
+        mut grounded: Option<&mut Grounded>,
+        velocity: Vector3<f32>,
+    ) -> Option<Vector3<f32>> {
+        let now = time.absolute_time_seconds();
+        let mut new_velocity = None;
+
+        if let Some(ref ground) = grounded {
+            if ground.ground {
+                self.air_jumps_left = self.air_jumps;
+            }
+        }
+
+        let just_pressed = pressed && !self.input_hold;
+        if just_pressed {
+            // We just started pressing the key. Registering time.
+            self.last_physical_press = now;
+        }
+        if !pressed && self.input_hold && self.rising && velocity.y > 0.0 {
+            // Released early, cut the jump short.
+            if let Some(cut) = self.jump_cut {
+                new_velocity = Some(Vector3::new(velocity.x, velocity.y * cut, velocity.z));
+            }
+        }
+        self.input_hold = pressed;
+        if !pressed || velocity.y <= 0.0 {
+            self.rising = false;
+        }
+
+        // Holding the jump key only jumps again on an auto jump controller.
+        let mut wants_jump = false;
+        if pressed && (just_pressed || self.auto_jump) {
+            // The last time we jumped wasn't long enough ago
+            if now - self.last_logical_press >= self.input_cooldown {
+                self.last_logical_press = now;
+                wants_jump = true;
+                if just_pressed {
+                    self.buffered_press = true;
+                }
+            } else if just_pressed {
+                // Ignored presses aren't buffered either.
+                self.buffered_press = false;
+            }
+        }
+        if self.buffered_press && now - self.last_physical_press <= self.jump_buffer {
+            wants_jump = true;
+        }
+        if !wants_jump {
+            return new_velocity;
+        }
+
+        // If we need to check for it, verify that we are on the ground.
+        let mut grounded_since = now;
+        let mut air_jump = false;
+        if self.check_ground {
+            match grounded {
+                Some(ref ground) if ground.ground || self.in_coyote_time(ground, now) => {
+                    grounded_since = ground.since;
+                }
+                Some(_) if self.air_jumps_left > 0 => air_jump = true,
+                _ => return new_velocity,
+            }
+        }
+
+        if now - self.last_jump <= self.jump_cooldown {
+            return new_velocity;
+        }
+
+        // Jump!
+        self.last_jump = now;
+        self.buffered_press = false;
+        self.rising = pressed;
+        if air_jump {
+            self.air_jumps_left -= 1;
+        }
+        // Offset for jump. Positive = time when we jumped AFTER we hit the ground.
+        self.last_jump_offset = grounded_since - self.last_physical_press;
+
+        let multiplier = if let Some(ref curve) = self.jump_timing_boost {
+            curve.eval(self.last_jump_offset).unwrap_or(1.0)
+        } else {
+            1.0
+        };
+
+        let v = new_velocity.unwrap_or(velocity);
+        let jumped = if !self.absolute && !air_jump {
+            v + Vector3::<f32>::y() * self.jump_force * multiplier
+        } else {
+            Vector3::new(v.x, self.jump_force * multiplier, v.z)
+        };
+
+        if let Some(ref mut ground) = grounded {
+            ground.ground = false;
+        }
+        Some(jumped)
+    }
 }
 
 impl Component for Jump {
     type Storage = DenseVecStorage<Self>;
 }
 
-#[derive(Default)]
-pub struct JumpSystem {
-    /// The last time the system considered a valid jump input.
-    last_logical_press: f64,
-    /// Was the jump key pressed last frame?
-    input_hold: bool,
-    /// The last time we physically pressed the jump key.
-    last_physical_press: f64,
+/// Overrides the action used by the `JumpSystem` for this entity.
+/// Use it to give each local player their own jump key.
+#[derive(Debug, Clone, new)]
+pub struct JumpAction<B: BindingTypes> {
+    pub action: B::Action,
+}
+
+impl<B: BindingTypes> Component for JumpAction<B> {
+    type Storage = DenseVecStorage<Self>;
+}
+
+/// Makes the entities having a `Jump` component and a rigid body jump when their action is pressed.
+/// Generic parameter is the parameter for the InputHandler.
+#[derive(new)]
+pub struct JumpSystem<B: BindingTypes> {
+    /// The action used by the entities that don't have a `JumpAction` component.
+    jump_action: B::Action,
 }
 
-impl<'a> System<'a> for JumpSystem {
+impl<'a, B: BindingTypes> System<'a> for JumpSystem<B> {
     type SystemData = (
         Entities<'a>,
         WriteStorage<'a, Grounded>,
         WriteStorage<'a, Jump>,
+        ReadStorage<'a, JumpAction<B>>,
         Read<'a, Time>,
-        Read<'a, InputHandler<StringBindings>>,
+        Read<'a, InputHandler<B>>,
         WriteRigidBodies<'a, f32>,
     );
 
     fn run(
         &mut self,
-        (entities, mut grounded, mut jumps, time, input, mut rigid_bodies): Self::SystemData,
+        (entities, mut grounded, mut jumps, actions, time, input, mut rigid_bodies): Self::SystemData,
     ) {
-        if let Some(true) = input.action_is_down("jump") {
-            if !self.input_hold {
-                // We just started pressing the key. Registering time.
-                self.last_physical_press = time.absolute_time_seconds();
-                self.input_hold = true;
+        for (entity, mut jump, rb) in (&*entities, &mut jumps, &mut rigid_bodies).join() {
+            let action = actions
+                .get(entity)
+                .map(|a| &a.action)
+                .unwrap_or(&self.jump_action);
+            let pressed = input.action_is_down(action).unwrap_or(false);
+            if let Some(velocity) = jump.update(
+                pressed,
+                &time,
+                grounded.get_mut(entity),
+                rb.velocity().linear,
+            ) {
+                rb.set_linear_velocity(velocity);
             }
+        }
+    }
+}
 
-            for (entity, mut jump, rb) in (&*entities, &mut jumps, &mut rigid_bodies).join() {
-                // Holding the jump key on a non-auto jump controller.
-                if self.input_hold && !jump.auto_jump {
-                    continue;
-                }
+#[cfg(test)]
+mod test {
+    use crate::movement::*;
 
-                // The last time we jumped wasn't long enough ago
-                if time.absolute_time_seconds() - self.last_logical_press < jump.input_cooldown {
-                    continue;
-                }
-                self.last_logical_press = time.absolute_time_seconds();
-
-                // If we need to check for it, verify that we are on the ground.
-                let mut grounded_since = time.absolute_time_seconds();
-                if jump.check_ground {
-                    if let Some(ground) = grounded.get(entity) {
-                        if !ground.ground {
-                            continue;
-                        }
-                        grounded_since = ground.since;
-                    } else {
-                        continue;
-                    }
-                }
+    use amethyst::core::math::Vector3;
+    use amethyst::core::timing::Time;
 
-                if time.absolute_time_seconds() - jump.last_jump > jump.jump_cooldown {
-                    // Jump!
-                    jump.last_jump = time.absolute_time_seconds();
-                    // Offset for jump. Positive = time when we jumped AFTER we hit the ground.
-                    jump.last_jump_offset = grounded_since - self.last_physical_press;
-
-                    let multiplier = if let Some(ref curve) = jump.jump_timing_boost {
-                        curve.eval(jump.last_jump_offset).unwrap_or(1.0)
-                    } else {
-                        1.0
-                    };
-
-                    if !jump.absolute {
-                        rb.set_linear_velocity(
-                            rb.velocity().linear
-                                + Vector3::<f32>::y() * jump.jump_force * multiplier,
-                        );
-                    } else {
-                        let (x, z) = {
-                            let v = rb.velocity().linear;
-                            (v.x, v.z)
-                        };
-                        rb.set_linear_velocity(Vector3::new(x, jump.jump_force * multiplier, z));
-                    }
-                }
-                if let Some(ref mut ground) = grounded.get_mut(entity) {
-                    ground.ground = false;
-                }
-            }
-        } else {
-            // The jump key was released.
-            self.input_hold = false;
+    use std::time::Duration;
+
+    /// 1/64th of a second, exact in floating point.
+    fn step(time: &mut Time, frames: u32) {
+        for _ in 0..frames {
+            time.set_delta_time(Duration::from_nanos(15_625_000));
         }
     }
+
+    fn jump() -> Jump {
+        let mut jump = Jump::new(false, true, 5.0, false);
+        jump.jump_cooldown = 0.0;
+        jump.input_cooldown = 0.0;
+        jump
+    }
+
+    fn on_ground(time: &Time) -> Grounded {
+        let mut grounded = Grounded::new(1.0, None);
+        grounded.ground = true;
+        grounded.since = time.absolute_time_seconds();
+        grounded
+    }
+
+    #[test]
+    fn buffered_jump() {
+        let mut time = Time::default();
+        step(&mut time, 64);
+        let mut jump = jump();
+        let mut grounded = Grounded::new(1.0, None);
+        let falling = Vector3::new(0.0, -3.0, 0.0);
+
+        // Pressed and released just before landing.
+        assert_eq!(jump.update(true, &time, Some(&mut grounded), falling), None);
+        step(&mut time, 1);
+        assert_eq!(
+            jump.update(false, &time, Some(&mut grounded), falling),
+            None
+        );
+        step(&mut time, 3);
+        grounded = on_ground(&time);
+        assert_eq!(
+            jump.update(false, &time, Some(&mut grounded), Vector3::zeros()),
+            Some(Vector3::new(0.0, 5.0, 0.0))
+        );
+        assert!(!grounded.ground);
+
+        // Pressed too early.
+        step(&mut time, 64);
+        grounded = Grounded::new(1.0, None);
+        assert_eq!(jump.update(true, &time, Some(&mut grounded), falling), None);
+        step(&mut time, 10);
+        grounded = on_ground(&time);
+        assert_eq!(
+            jump.update(true, &time, Some(&mut grounded), Vector3::zeros()),
+            None
+        );
+    }
+
+    #[test]
+    fn input_cooldown_blocks_buffered_press() {
+        let mut time = Time::default();
+        step(&mut time, 64);
+        let mut jump = jump();
+        jump.input_cooldown = 0.1;
+        let mut grounded = on_ground(&time);
+        let falling = Vector3::new(0.0, -3.0, 0.0);
+
+        assert!(jump
+            .update(true, &time, Some(&mut grounded), Vector3::zeros())
+            .is_some());
+        step(&mut time, 1);
+        assert_eq!(
+            jump.update(false, &time, Some(&mut grounded), falling),
+            None
+        );
+        // Pressed again too soon, then landing in the buffer window.
+        step(&mut time, 1);
+        assert_eq!(jump.update(true, &time, Some(&mut grounded), falling), None);
+        step(&mut time, 2);
+        grounded = on_ground(&time);
+        assert_eq!(
+            jump.update(false, &time, Some(&mut grounded), Vector3::zeros()),
+            None
+        );
+    }
+
+    #[test]
+    fn variable_jump_height() {
+        let mut time = Time::default();
+        step(&mut time, 64);
+        let mut jump = jump();
+        jump.jump_cut = Some(0.5);
+        let mut grounded = on_ground(&time);
+
+        assert_eq!(
+            jump.update(true, &time, Some(&mut grounded), Vector3::zeros()),
+            Some(Vector3::new(0.0, 5.0, 0.0))
+        );
+        step(&mut time, 1);
+        let rising = Vector3::new(1.0, 4.0, 0.0);
+        assert_eq!(jump.update(true, &time, Some(&mut grounded), rising), None);
+        step(&mut time, 1);
+        assert_eq!(
+            jump.update(false, &time, Some(&mut grounded), rising),
+            Some(Vector3::new(1.0, 2.0, 0.0))
+        );
+        // Only cut once.
+        step(&mut time, 1);
+        assert_eq!(jump.update(false, &time, Some(&mut grounded), rising), None);
+    }
+
+    #[test]
+    fn air_jumps_reset_on_landing() {
+        let mut time = Time::default();
+        step(&mut time, 64);
+        let mut jump = jump();
+        jump.air_jumps = 1;
+        let mut grounded = on_ground(&time);
+        let falling = Vector3::new(0.0, -3.0, 0.0);
+
+        assert!(jump
+            .update(true, &time, Some(&mut grounded), Vector3::zeros())
+            .is_some());
+        step(&mut time, 32);
+        assert_eq!(
+            jump.update(false, &time, Some(&mut grounded), falling),
+            None
+        );
+        step(&mut time, 1);
+        // Air jumps set the vertical velocity.
+        assert_eq!(
+            jump.update(true, &time, Some(&mut grounded), falling),
+            Some(Vector3::new(0.0, 5.0, 0.0))
+        );
+        step(&mut time, 1);
+        assert_eq!(
+            jump.update(false, &time, Some(&mut grounded), falling),
+            None
+        );
+        step(&mut time, 1);
+        assert_eq!(jump.update(true, &time, Some(&mut grounded), falling), None);
+        assert_eq!(jump.air_jumps_left, 0);
+
+        // Landing after the buffer expired gives the air jump back.
+        step(&mut time, 32);
+        grounded = on_ground(&time);
+        assert_eq!(
+            jump.update(false, &time, Some(&mut grounded), Vector3::zeros()),
+            None
+        );
+        assert_eq!(jump.air_jumps_left, 1);
+    }
 }
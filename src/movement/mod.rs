@@ -3,6 +3,7 @@ mod ground_friction;
 mod jump;
 mod movement;
 mod rotation;
+mod stance;
 mod upright;
 mod utils;
 
@@ -11,5 +12,6 @@ pub use self::ground_friction::*;
 pub use self::jump::*;
 pub use self::movement::*;
 pub use self::rotation::*;
+pub use self::stance::*;
 pub use self::upright::*;
 pub use self::utils::*;
@@ -38,21 +38,26 @@ impl<'a, B: BindingTypes> System<'a> for FpsMovementSystemSimple<B> {
         WriteStorage<'a, Transform>,
         Read<'a, InputHandler<B>>,
         ReadStorage<'a, FpsMovement>,
+        ReadStorage<'a, Stance>,
         WriteRigidBodies<'a, f32>,
     );
 
-    fn run(&mut self, (time, transforms, input, tags, mut rigid_bodies): Self::SystemData) {
+    fn run(
+        &mut self,
+        (time, transforms, input, tags, stances, mut rigid_bodies): Self::SystemData,
+    ) {
         let x = get_input_axis_simple(&self.right_input_axis, &input);
         let z = get_input_axis_simple(&self.forward_input_axis, &input);
 
         let dir = Vector3::new(x, 0.0, z);
         if dir.magnitude() != 0.0 {
-            for (transform, tag, rb) in (&transforms, &tags, &mut rigid_bodies).join() {
+            for (transform, tag, stance, rb) in
+                (&transforms, &tags, stances.maybe(), &mut rigid_bodies).join()
+            {
                 let mut dir: Vector3<f32> = transform.rotation() * dir;
                 dir = dir.normalize();
-                rb.set_linear_velocity(
-                    rb.velocity().linear + dir * tag.speed * time.delta_seconds(),
-                );
+                let speed = tag.speed * stance.map(|s| s.speed_multiplier()).unwrap_or(1.0);
+                rb.set_linear_velocity(rb.velocity().linear + dir * speed * time.delta_seconds());
             }
         }
     }
@@ -104,23 +109,35 @@ impl<'a, B: BindingTypes> System<'a> for BhopMovementSystem<B> {
         ReadStorage<'a, Transform>,
         ReadStorage<'a, BhopMovement3D>,
         ReadStorage<'a, Grounded>,
+        ReadStorage<'a, Stance>,
         WriteRigidBodies<'a, f32>,
     );
 
     fn run(
         &mut self,
-        (time, input, transforms, movements, groundeds, mut rigid_bodies): Self::SystemData,
+        (time, input, transforms, movements, groundeds, stances, mut rigid_bodies): Self::SystemData,
     ) {
         let x = get_input_axis_simple(&self.right_input_axis, &input);
         let z = get_input_axis_simple(&self.forward_input_axis, &input);
         let input = Vector2::new(x, z);
 
         if input.magnitude() != 0.0 {
-            for (transform, movement, grounded, rb) in
-                (&transforms, &movements, &groundeds, &mut rigid_bodies).join()
+            for (transform, movement, grounded, stance, rb) in (
+                &transforms,
+                &movements,
+                &groundeds,
+                stances.maybe(),
+                &mut rigid_bodies,
+            )
+                .join()
             {
                 let (acceleration, max_velocity) = if grounded.ground {
-                    (movement.accelerate_ground, movement.max_velocity_ground)
+                    // Crouching and sprinting only change the ground speed.
+                    let multiplier = stance.map(|s| s.speed_multiplier()).unwrap_or(1.0);
+                    (
+                        movement.accelerate_ground * multiplier,
+                        movement.max_velocity_ground * multiplier,
+                    )
                 } else {
                     (movement.accelerate_air, movement.max_velocity_air)
                 };
@@ -153,7 +170,14 @@ impl<'a, B: BindingTypes> System<'a> for BhopMovementSystem<B> {
                 };
 
                 // Global to local coords;
-                let new_vel = transform.rotation() * new_vel_rel;
+                let mut new_vel = transform.rotation() * new_vel_rel;
+
+                // Sliding on a steep slope (surf ramp): don't push into it.
+                if grounded.sliding {
+                    if let Some(normal) = grounded.normal {
+                        new_vel = clip_velocity(new_vel, normal);
+                    }
+                }
 
                 // Assign the new velocity to the player
                 rb.set_linear_velocity(new_vel);
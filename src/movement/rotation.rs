@@ -23,6 +23,14 @@ impl Component for RotationControl {
     type Storage = DenseVecStorage<Self>;
 }
 
+/// Overrides the sensitivity given to the `FPSRotationRhusicsSystem` when present,
+/// for example to change it from the console using a `Cvar`.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, new)]
+pub struct MouseSensitivity {
+    pub x: f32,
+    pub y: f32,
+}
+
 /// The system that manages the view rotation.
 /// Controlled by the mouse.
 /// Put the RotationControl component on the Camera. The Camera should be a child of the player collider entity.
@@ -64,6 +72,7 @@ where
         Read<'a, WindowFocus>,
         Read<'a, HideCursor>,
         ReadStorage<'a, Parent>,
+        Option<Read<'a, MouseSensitivity>>,
     );
 
     fn run(
@@ -76,11 +85,15 @@ where
             focus,
             hide,
             parents,
+            sensitivity,
         ): Self::SystemData,
     ) {
         if self.event_reader.is_none() {
             self.event_reader = Some(events.register_reader());
         }
+        let (sensitivity_x, sensitivity_y) = sensitivity
+            .map(|s| (s.x, s.y))
+            .unwrap_or((self.sensitivity_x, self.sensitivity_y));
         let focused = focus.is_focused;
         let win_events = events
             .read(&mut self.event_reader.as_mut().unwrap())
@@ -109,8 +122,8 @@ where
                             for (entity, mut rotation_control, parent) in
                                 (&*entities, &mut rotation_controls, &parents).join()
                             {
-                                rotation_control.mouse_accum_x -= x as f32 * self.sensitivity_x;
-                                rotation_control.mouse_accum_y -= y as f32 * self.sensitivity_y;
+                                rotation_control.mouse_accum_x -= x as f32 * sensitivity_x;
+                                rotation_control.mouse_accum_y -= y as f32 * sensitivity_y;
                                 // Limit maximum vertical angle to prevent locking the quaternion and/or going upside down.
                                 // rotation_control.mouse_accum_y = rotation_control.mouse_accum_y.max(-89.5).min(89.5);
                                 rotation_control.mouse_accum_y = rotation_control
@@ -0,0 +1,369 @@
+use crate::movement::ground::Grounded;
+
+use ::amethyst::core::math::Isometry3;
+use ::amethyst::core::timing::Time;
+use ::amethyst::ecs::*;
+use ::amethyst::input::*;
+
+use serde::Serialize;
+
+use nphysics_ecs::ncollide::pipeline::CollisionGroups;
+use nphysics_ecs::ncollide::query::{self, Proximity};
+use nphysics_ecs::ncollide::shape::ShapeHandle;
+use nphysics_ecs::*;
+use specs_physics::colliders::ColliderComponent;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StanceState {
+    Standing,
+    Crouching,
+    Sprinting,
+}
+
+impl Default for StanceState {
+    fn default() -> Self {
+        StanceState::Standing
+    }
+}
+
+/// Lets the entity crouch and sprint. Updated by the `StanceSystem`.
+/// The speed multipliers are applied by the `BhopMovementSystem` and the `FpsMovementSystemSimple`.
+///
+/// The crouching shape replaces the shape of the entity's collider. Both shapes are centered on the entity,
+/// so crouching in the air lifts the feet (crouch-jump) while crouching on the ground drops the entity to the floor.
+/// Standing up on the ground lifts the entity back up, so that its feet stay on the floor.
+#[derive(Clone, new)]
+pub struct Stance {
+    pub standing_shape: ShapeHandle<f32>,
+    pub crouching_shape: ShapeHandle<f32>,
+    /// Multiplies the ground speed while crouching.
+    #[new(value = "0.5")]
+    pub crouch_speed_multiplier: f32,
+    /// Multiplies the ground speed while sprinting.
+    #[new(value = "1.5")]
+    pub sprint_speed_multiplier: f32,
+    /// Allows starting to crouch in the air, to crouch-jump over higher obstacles.
+    #[new(value = "true")]
+    pub air_crouch: bool,
+    #[new(default)]
+    pub state: StanceState,
+    /// Wants to stand up, but something is in the way.
+    #[new(default)]
+    pub obstructed: bool,
+}
+
+impl Stance {
+    /// The multiplier to apply to the speed caps in the current state.
+    pub fn speed_multiplier(&self) -> f32 {
+        match self.state {
+            StanceState::Standing => 1.0,
+            StanceState::Crouching => self.crouch_speed_multiplier,
+            StanceState::Sprinting => self.sprint_speed_multiplier,
+        }
+    }
+
+    /// Decides if the entity crouches this frame, and updates `obstructed`.
+    /// `obstructed` tells if something is in the way of the standing shape. It is only called when standing up.
+    pub fn update_crouch(
+        &mut self,
+        crouch_down: bool,
+        on_ground: bool,
+        obstructed: impl FnOnce(&Self) -> bool,
+    ) -> bool {
+        let was_crouching = self.state == StanceState::Crouching;
+        if crouch_down && (on_ground || self.air_crouch || was_crouching) {
+            self.obstructed = false;
+            true
+        } else if was_crouching {
+            self.obstructed = obstructed(self);
+            self.obstructed
+        } else {
+            false
+        }
+    }
+
+    /// How much higher the center of the standing shape is than the center of the crouching shape,
+    /// when both rest on the same floor.
+    pub fn stand_up_offset(&self) -> f32 {
+        let identity = Isometry3::identity();
+        self.standing_shape.aabb(&identity).half_extents().y
+            - self.crouching_shape.aabb(&identity).half_extents().y
+    }
+
+    /// Where the standing shape would be if the entity stood up from the crouched `position`.
+    pub fn standing_position(&self, position: &Isometry3<f32>, on_ground: bool) -> Isometry3<f32> {
+        let mut standing = *position;
+        if on_ground {
+            standing.translation.vector.y += self.stand_up_offset();
+        }
+        standing
+    }
+}
+
+impl Component for Stance {
+    type Storage = DenseVecStorage<Self>;
+}
+
+/// Limits sprinting. Entities with a `Stance` but no `Stamina` can sprint forever.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Stamina {
+    pub max: f32,
+    pub current: f32,
+    /// Stamina lost per second while sprinting.
+    pub drain: f32,
+    /// Stamina regained per second while not sprinting.
+    pub regen: f32,
+    /// The stamina needed to start sprinting, so that an exhausted entity doesn't flicker between sprinting and walking.
+    pub min_to_sprint: f32,
+}
+
+impl Stamina {
+    pub fn new(max: f32, drain: f32, regen: f32) -> Self {
+        Stamina {
+            max,
+            current: max,
+            drain,
+            regen,
+            min_to_sprint: max * 0.2,
+        }
+    }
+
+    /// Drains or regenerates the stamina. Returns if the entity can sprint.
+    pub fn update(&mut self, wants_sprint: bool, was_sprinting: bool, delta_seconds: f32) -> bool {
+        let sprinting = wants_sprint
+            && self.current > 0.0
+            && (was_sprinting || self.current >= self.min_to_sprint);
+        if sprinting {
+            self.current = (self.current - self.drain * delta_seconds).max(0.0);
+        } else {
+            self.current = (self.current + self.regen * delta_seconds).min(self.max);
+        }
+        sprinting
+    }
+}
+
+impl Component for Stamina {
+    type Storage = DenseVecStorage<Self>;
+}
+
+/// Overrides the actions used by the `StanceSystem` for this entity.
+/// Use it to give each local player their own crouch and sprint keys.
+#[derive(Debug, Clone, new)]
+pub struct StanceActions<B: BindingTypes> {
+    /// The action held to crouch. None can't crouch.
+    pub crouch_action: Option<B::Action>,
+    /// The action held to sprint. None can't sprint.
+    pub sprint_action: Option<B::Action>,
+}
+
+impl<B: BindingTypes> Component for StanceActions<B> {
+    type Storage = DenseVecStorage<Self>;
+}
+
+/// Checks if the shape would touch a collider other than the entity's own if placed at `position`.
+fn obstructed(
+    entity: Entity,
+    shape: &ShapeHandle<f32>,
+    position: &Isometry3<f32>,
+    colliders: &WriteStorage<ColliderComponent<f32>>,
+    geometrical_world: &GeometricalWorldRes<f32>,
+) -> bool {
+    let aabb = shape.aabb(position);
+    geometrical_world
+        .interferences_with_aabb(colliders, &aabb, &CollisionGroups::new())
+        .any(|(other, collider)| {
+            other != entity
+                && !collider.is_sensor()
+                && query::proximity(
+                    position,
+                    &**shape,
+                    collider.position(),
+                    &*collider.shape(),
+                    0.0,
+                ) == Proximity::Intersecting
+        })
+}
+
+/// Switches the `Stance` of the entities according to the input, and swaps their collider shape.
+/// Standing up is delayed until nothing is in the way of the standing shape.
+/// Entities standing up on the ground are moved up by `Stance::stand_up_offset`.
+/// Generic parameters are the parameters for the InputHandler.
+#[derive(new)]
+pub struct StanceSystem<B: BindingTypes> {
+    /// The action held to crouch by the entities that don't have a `StanceActions` component.
+    crouch_action: Option<B::Action>,
+    /// The action held to sprint by the entities that don't have a `StanceActions` component.
+    sprint_action: Option<B::Action>,
+}
+
+impl<'a, B: BindingTypes> System<'a> for StanceSystem<B> {
+    type SystemData = (
+        Entities<'a>,
+        Read<'a, Time>,
+        Read<'a, InputHandler<B>>,
+        ReadStorage<'a, StanceActions<B>>,
+        WriteStorage<'a, Stance>,
+        WriteStorage<'a, Stamina>,
+        ReadStorage<'a, Grounded>,
+        WriteStorage<'a, ColliderComponent<f32>>,
+        ReadExpect<'a, GeometricalWorldRes<f32>>,
+        WriteRigidBodies<'a, f32>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            entities,
+            time,
+            input,
+            actions,
+            mut stances,
+            mut staminas,
+            groundeds,
+            mut colliders,
+            geometrical_world,
+            mut rigid_bodies,
+        ): Self::SystemData,
+    ) {
+        let is_down = |action: &Option<B::Action>| {
+            action
+                .as_ref()
+                .and_then(|a| input.action_is_down(a))
+                .unwrap_or(false)
+        };
+
+        for (entity, mut stance) in (&*entities, &mut stances).join() {
+            let (crouch_down, sprint_down) = match actions.get(entity) {
+                Some(a) => (is_down(&a.crouch_action), is_down(&a.sprint_action)),
+                None => (is_down(&self.crouch_action), is_down(&self.sprint_action)),
+            };
+            let was_crouching = stance.state == StanceState::Crouching;
+            let on_ground = groundeds.get(entity).map(|g| g.ground).unwrap_or(true);
+
+            let crouching = stance.update_crouch(crouch_down, on_ground, |stance| {
+                let position = match colliders.get(entity) {
+                    Some(collider) => stance.standing_position(collider.position(), on_ground),
+                    None => return false,
+                };
+                obstructed(
+                    entity,
+                    &stance.standing_shape,
+                    &position,
+                    &colliders,
+                    &geometrical_world,
+                )
+            });
+
+            let wants_sprint = sprint_down && !crouching;
+            let sprinting = match staminas.get_mut(entity) {
+                Some(stamina) => stamina.update(
+                    wants_sprint,
+                    stance.state == StanceState::Sprinting,
+                    time.delta_seconds(),
+                ),
+                None => wants_sprint,
+            };
+
+            if crouching != was_crouching {
+                let shape = if crouching {
+                    stance.crouching_shape.clone()
+                } else {
+                    stance.standing_shape.clone()
+                };
+                if let Some(collider) = colliders.get_mut(entity) {
+                    collider.set_shape(shape);
+                }
+                if !crouching && on_ground {
+                    // Keep the feet on the floor.
+                    if let Some(rb) = rigid_bodies.get_mut(entity) {
+                        let position = stance.standing_position(rb.position(), on_ground);
+                        rb.set_position(position);
+                    }
+                }
+            }
+
+            stance.state = if crouching {
+                StanceState::Crouching
+            } else if sprinting {
+                StanceState::Sprinting
+            } else {
+                StanceState::Standing
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::movement::*;
+
+    use amethyst::core::math::{Isometry3, Vector3};
+    use nphysics_ecs::ncollide::query::{self, Proximity};
+    use nphysics_ecs::ncollide::shape::{Cuboid, ShapeHandle};
+
+    fn stance() -> Stance {
+        Stance::new(
+            ShapeHandle::new(Cuboid::new(Vector3::new(0.5, 0.9, 0.5))),
+            ShapeHandle::new(Cuboid::new(Vector3::new(0.5, 0.45, 0.5))),
+        )
+    }
+
+    #[test]
+    fn stand_up_when_unobstructed() {
+        let mut stance = stance();
+        assert!(stance.update_crouch(true, true, |_| panic!("Not standing up")));
+        stance.state = StanceState::Crouching;
+        // Released under something.
+        assert!(stance.update_crouch(false, true, |_| true));
+        assert!(stance.obstructed);
+        assert!(stance.update_crouch(false, true, |_| true));
+        // Moved out from under it.
+        assert!(!stance.update_crouch(false, true, |_| false));
+        assert!(!stance.obstructed);
+        stance.state = StanceState::Standing;
+        // Can't start crouching in the air.
+        stance.air_crouch = false;
+        assert!(!stance.update_crouch(true, false, |_| false));
+    }
+
+    #[test]
+    fn standing_shape_clears_the_floor() {
+        let stance = stance();
+        assert!((stance.stand_up_offset() - 0.45).abs() < 1e-6);
+        let floor = Cuboid::new(Vector3::new(10.0, 0.5, 10.0));
+        let floor_position = Isometry3::translation(0.0, -0.5, 0.0);
+        // Crouching just above the floor.
+        let crouched = Isometry3::translation(0.0, 0.46, 0.0);
+        let intersects = |position: &Isometry3<f32>| {
+            query::proximity(
+                position,
+                &*stance.standing_shape,
+                &floor_position,
+                &floor,
+                0.0,
+            ) == Proximity::Intersecting
+        };
+        assert!(intersects(&crouched));
+        let standing = stance.standing_position(&crouched, true);
+        assert!((standing.translation.vector.y - 0.91).abs() < 1e-6);
+        assert!(!intersects(&standing));
+        // In the air, the shape grows from its center.
+        assert_eq!(stance.standing_position(&crouched, false), crouched);
+    }
+
+    #[test]
+    fn stamina_stops_and_restarts_sprint() {
+        let mut stamina = Stamina::new(10.0, 5.0, 1.0);
+        assert!(stamina.update(true, false, 1.0));
+        assert!(stamina.update(true, true, 1.0));
+        assert_eq!(stamina.current, 0.0);
+        assert!(!stamina.update(true, true, 1.0));
+        assert_eq!(stamina.current, 1.0);
+        // Not enough stamina to start sprinting again.
+        assert!(!stamina.update(true, false, 0.5));
+        assert!(!stamina.update(true, false, 0.5));
+        assert_eq!(stamina.current, 2.0);
+        assert!(stamina.update(true, false, 0.2));
+        assert_eq!(stamina.current, 1.0);
+    }
+}
@@ -57,3 +57,14 @@ pub fn limit_velocity(vec: Vector3<f32>, maximum_velocity: f32) -> Vector3<f32>
     }
     vec
 }
+
+/// Removes the part of the velocity going into a surface, so that the movement slides along it.
+/// Used to surf on ramps too steep to stand on.
+pub fn clip_velocity(vec: Vector3<f32>, normal: Vector3<f32>) -> Vector3<f32> {
+    let into = vec.dot(&normal);
+    if into < 0.0 {
+        vec - normal * into
+    } else {
+        vec
+    }
+}
@@ -1,3 +1,5 @@
+use crate::terminal::Command;
+
 use ::amethyst::controls::FlyControlTag;
 
 use ::amethyst::renderer::{camera::ActiveCamera, Camera};
@@ -23,6 +25,37 @@ where
     pub(crate) previous_active_camera: Option<Entity>,
     #[new(default)]
     pub(crate) active: bool,
+    #[new(default)]
+    #[serde(skip)]
+    pub(crate) toggle_requested: bool,
+}
+
+impl<T> NoClip<T>
+where
+    T: BindingTypes,
+{
+    pub fn active(&self) -> bool {
+        self.active
+    }
+
+    /// Toggles the noclip camera during the next run of the `NoClipToggleSystem`, as if the toggle action was pressed.
+    pub fn request_toggle(&mut self) {
+        self.toggle_requested = true;
+    }
+}
+
+/// Creates the `noclip` console command, toggling the noclip camera.
+pub fn noclip_command<T>() -> Command
+where
+    T: BindingTypes,
+{
+    Command::new("noclip", "Toggles the noclip camera.").handler(|_, world| {
+        world
+            .try_fetch_mut::<NoClip<T>>()
+            .map(|mut n| n.request_toggle())
+            .ok_or_else(|| String::from("Noclip is not available."))?;
+        Ok(String::new())
+    })
 }
 
 #[derive(Default, new, Serialize, Deserialize, Clone, Copy)]
@@ -92,45 +125,51 @@ where
 
         // TODO: AutoFov support
 
+        let mut toggle = noclip_res.toggle_requested;
+        noclip_res.toggle_requested = false;
         for event in events.read(&mut self.event_reader) {
-            match event {
-                InputEvent::ActionPressed(key) => {
-                    if *key == noclip_res.toggle_action_key {
-                        if !noclip_res.active {
-                            // Enable noclip
-                            let entity = entities.create();
-                            let transform = Transform::default(); // TODO: get global position of current main entity.
-                            transforms.insert(entity, transform).unwrap();
-                            fly_control_tags.insert(entity, FlyControlTag).unwrap();
-                            cameras
-                                .insert(entity, Camera::standard_3d(800.0, 600.0))
-                                .unwrap(); // TODO: clone main camera if available.
-                            noclips.insert(entity, NoClipTag).unwrap();
-
-                            active_camera.entity = Some(entity);
-                            noclip_res.noclip_entity = Some(entity);
-
-                            noclip_res.active = true;
-                        } else {
-                            // Disable noclip
-
-                            // get noclip entity
-                            if let Some(entity) = noclip_res.noclip_entity {
-                                if let Err(err) = entities.delete(entity) {
-                                    error!("Noclip is enabled, but there is no noclip entity in the world! {}", err);
-                                }
-
-                                active_camera.entity = noclip_res.previous_active_camera.clone();
-                                noclip_res.noclip_entity = None;
-                            } else {
-                                error!("Noclip is enabled, but there is no noclip entity in the world!");
-                            }
-
-                            noclip_res.active = false;
-                        }
+            if let InputEvent::ActionPressed(key) = event {
+                if *key == noclip_res.toggle_action_key {
+                    toggle = !toggle;
+                }
+            }
+        }
+
+        if toggle {
+            if !noclip_res.active {
+                // Enable noclip
+                let entity = entities.create();
+                let transform = Transform::default(); // TODO: get global position of current main entity.
+                transforms.insert(entity, transform).unwrap();
+                fly_control_tags.insert(entity, FlyControlTag).unwrap();
+                cameras
+                    .insert(entity, Camera::standard_3d(800.0, 600.0))
+                    .unwrap(); // TODO: clone main camera if available.
+                noclips.insert(entity, NoClipTag).unwrap();
+
+                active_camera.entity = Some(entity);
+                noclip_res.noclip_entity = Some(entity);
+
+                noclip_res.active = true;
+            } else {
+                // Disable noclip
+
+                // get noclip entity
+                if let Some(entity) = noclip_res.noclip_entity {
+                    if let Err(err) = entities.delete(entity) {
+                        error!(
+                            "Noclip is enabled, but there is no noclip entity in the world! {}",
+                            err
+                        );
                     }
+
+                    active_camera.entity = noclip_res.previous_active_camera.clone();
+                    noclip_res.noclip_entity = None;
+                } else {
+                    error!("Noclip is enabled, but there is no noclip entity in the world!");
                 }
-                _ => {}
+
+                noclip_res.active = false;
             }
         }
     }
@@ -0,0 +1,565 @@
+use crate::auto_save::ShouldSave;
+use crate::error::Error;
+use crate::http::{duration_secs, HttpClientResource, HttpRequest, RetryPolicy};
+
+use ::amethyst::core::timing::Time;
+use ::amethyst::ecs::*;
+use ::amethyst::shrev::EventChannel;
+
+use dirty::Dirty;
+
+use hyper::Method;
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Where a queued request is in its delivery.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DeliveryStatus {
+    /// Waiting to be sent, or to be sent again once online.
+    Pending,
+    /// Currently being sent.
+    InFlight,
+    /// The server accepted the request.
+    Delivered,
+    /// The server refused the request, or it failed too many times. It will not be sent again.
+    Failed,
+}
+
+/// Emitted each time a queued request is delivered or definitely failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeliveryEvent {
+    pub key: String,
+    pub status: DeliveryStatus,
+}
+
+/// A request waiting in the `OfflineQueue`. Saved as is to disk.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueuedRequest {
+    /// Requests with the same key replace each other.
+    pub key: String,
+    pub method: String,
+    /// The url, including the query parameters.
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    /// The number of failed attempts to send this request.
+    pub attempts: u32,
+    /// Incremented when the request is replaced by a newer one with the same key.
+    revision: u64,
+    #[serde(skip)]
+    in_flight: bool,
+}
+
+impl QueuedRequest {
+    fn to_request(&self) -> Result<HttpRequest, Error> {
+        let method = Method::from_bytes(self.method.as_bytes())
+            .map_err(|e| Error::InvalidRequest(e.to_string()))?;
+        let mut request = HttpRequest::new(method, &self.url).body(self.body.clone());
+        request.headers = self.headers.clone();
+        Ok(request)
+    }
+}
+
+/// Durable queue of outbound requests, for data that must reach the server eventually
+/// like leaderboard submissions or telemetry.
+///
+/// Persist it by adding an `AutoSaveSystem<OfflineQueue>` to your dispatcher and inserting
+/// the `Dirty<OfflineQueue>` it loads into the world. The `OfflineQueueSystem` sends the requests.
+///
+/// Usage:
+/// ```rs
+/// let request = HttpRequest::post_json("https://example.com/times", &time)?;
+/// world.write_resource::<Dirty<OfflineQueue>>().write().enqueue("map1/best", &request);
+/// ```
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct OfflineQueue {
+    requests: Vec<QueuedRequest>,
+    /// Give up on a request after this many failed attempts. 0 = retry forever.
+    pub max_attempts: u32,
+    #[serde(skip)]
+    statuses: HashMap<String, DeliveryStatus>,
+    #[serde(skip)]
+    offline: bool,
+    /// Consecutive failures caused by connectivity.
+    #[serde(skip)]
+    failures: u32,
+    next_revision: u64,
+    #[serde(skip)]
+    updated: bool,
+}
+
+impl OfflineQueue {
+    /// Queues the request. Replaces any queued request with the same key.
+    pub fn enqueue(&mut self, key: &str, request: &HttpRequest) {
+        self.next_revision += 1;
+        let queued = QueuedRequest {
+            key: key.to_string(),
+            method: request.method.as_str().to_string(),
+            url: request.full_url(),
+            headers: request.headers.clone(),
+            body: request.body.clone(),
+            attempts: 0,
+            revision: self.next_revision,
+            in_flight: false,
+        };
+        if let Some(existing) = self.requests.iter_mut().find(|r| r.key == key) {
+            *existing = queued;
+        } else {
+            self.requests.push(queued);
+        }
+        self.statuses
+            .insert(key.to_string(), DeliveryStatus::Pending);
+        self.updated = true;
+    }
+
+    /// Removes the request from the queue. An already in flight request can still be delivered.
+    pub fn remove(&mut self, key: &str) {
+        self.requests.retain(|r| r.key != key);
+        self.statuses.remove(key);
+        self.updated = true;
+    }
+
+    /// The delivery status of the request with this key, since the game started.
+    pub fn status(&self, key: &str) -> Option<DeliveryStatus> {
+        self.statuses.get(key).cloned().or_else(|| {
+            self.requests
+                .iter()
+                .find(|r| r.key == key)
+                .map(|_| DeliveryStatus::Pending)
+        })
+    }
+
+    pub fn requests(&self) -> &Vec<QueuedRequest> {
+        &self.requests
+    }
+
+    pub fn len(&self) -> usize {
+        self.requests.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.requests.is_empty()
+    }
+
+    /// False once a request failed because of connectivity, until the server answers one.
+    pub fn online(&self) -> bool {
+        !self.offline
+    }
+
+    /// Keys and revisions of the requests to send now.
+    /// When offline, only the oldest request is sent to probe the connection.
+    fn ready(&self) -> Vec<(String, u64)> {
+        let ready = self
+            .requests
+            .iter()
+            .filter(|r| !r.in_flight)
+            .map(|r| (r.key.clone(), r.revision));
+        if self.offline {
+            if self.requests.iter().any(|r| r.in_flight) {
+                return Vec::new();
+            }
+            ready.take(1).collect()
+        } else {
+            ready.collect()
+        }
+    }
+
+    fn set_in_flight(&mut self, key: &str) -> Option<&QueuedRequest> {
+        let request = self.requests.iter_mut().find(|r| r.key == key)?;
+        request.in_flight = true;
+        self.statuses
+            .insert(key.to_string(), DeliveryStatus::InFlight);
+        Some(request)
+    }
+
+    /// Updates the queue with the result of a request.
+    /// Returns the new status if the request is done, either delivered or failed.
+    fn complete<T>(
+        &mut self,
+        key: &str,
+        revision: u64,
+        result: &Result<T, Error>,
+    ) -> Option<DeliveryStatus> {
+        let idx = self.requests.iter().position(|r| r.key == key)?;
+        let replaced = self.requests[idx].revision != revision;
+        if !replaced {
+            // The newer request might be in flight too, it will complete on its own.
+            self.requests[idx].in_flight = false;
+        }
+        // Any http answer, even an error status, means the server is reachable.
+        let answered = match result {
+            Ok(_) | Err(Error::Status(_)) => true,
+            Err(_) => false,
+        };
+        if answered {
+            self.offline = false;
+            self.failures = 0;
+        }
+        let status = match result {
+            Ok(_) => DeliveryStatus::Delivered,
+            Err(e) if e.is_retryable() => {
+                warn!("Failed to send queued request {}: {}", key, e);
+                if !answered {
+                    self.offline = true;
+                    self.failures += 1;
+                }
+                if replaced {
+                    DeliveryStatus::Pending
+                } else {
+                    self.requests[idx].attempts += 1;
+                    if self.max_attempts != 0 && self.requests[idx].attempts >= self.max_attempts {
+                        DeliveryStatus::Failed
+                    } else {
+                        DeliveryStatus::Pending
+                    }
+                }
+            }
+            Err(e) => {
+                error!("Queued request {} was refused: {}", key, e);
+                DeliveryStatus::Failed
+            }
+        };
+        self.updated = true;
+        if replaced {
+            // A newer request with the same key is queued, it is the one we report on.
+            return None;
+        }
+        self.statuses.insert(key.to_string(), status);
+        if status == DeliveryStatus::Pending {
+            None
+        } else {
+            self.requests.remove(idx);
+            Some(status)
+        }
+    }
+}
+
+impl ShouldSave for OfflineQueue {
+    fn save_ready(&self) -> bool {
+        self.updated
+    }
+    fn set_save_ready(&mut self, ready: bool) {
+        self.updated = ready;
+    }
+}
+
+/// Sends the requests of the `Dirty<OfflineQueue>` resource through the `HttpClientResource`.
+/// While offline, the oldest request is periodically sent again, following the `RetryPolicy` backoff.
+/// When it succeeds, all the other requests are sent.
+pub struct OfflineQueueSystem {
+    /// Backoff between two probes while offline. `max_retries` is ignored.
+    pub backoff: RetryPolicy,
+    next_attempt: f64,
+}
+
+impl Default for OfflineQueueSystem {
+    fn default() -> Self {
+        OfflineQueueSystem::new(RetryPolicy::new(
+            0,
+            Duration::from_secs(2),
+            Duration::from_secs(120),
+        ))
+    }
+}
+
+impl OfflineQueueSystem {
+    pub fn new(backoff: RetryPolicy) -> Self {
+        OfflineQueueSystem {
+            backoff,
+            next_attempt: 0.0,
+        }
+    }
+}
+
+impl<'a> System<'a> for OfflineQueueSystem {
+    type SystemData = (
+        Write<'a, Dirty<OfflineQueue>>,
+        Option<Write<'a, HttpClientResource>>,
+        Read<'a, Time>,
+        Write<'a, EventChannel<DeliveryEvent>>,
+    );
+
+    fn run(&mut self, (mut queue, http, time, mut events): Self::SystemData) {
+        let mut http = match http {
+            Some(http) => http,
+            None => return,
+        };
+        let now = time.absolute_real_time_seconds();
+        if queue.read().offline && now < self.next_attempt {
+            return;
+        }
+        let ready = queue.read().ready();
+        if ready.is_empty() {
+            return;
+        }
+        if queue.read().offline {
+            let backoff = self.backoff.backoff(queue.read().failures.saturating_sub(1));
+            self.next_attempt = now + duration_secs(backoff);
+        }
+
+        let queue = queue.write();
+        for (key, revision) in ready {
+            let request = queue.set_in_flight(&key).map(QueuedRequest::to_request);
+            let request = match request {
+                Some(Ok(r)) => r,
+                Some(Err(e)) => {
+                    error!("Invalid queued request {}: {}", key, e);
+                    if let Some(status) = queue.complete::<()>(&key, revision, &Err(e)) {
+                        events.single_write(DeliveryEvent { key, status });
+                    }
+                    continue;
+                }
+                None => continue,
+            };
+            http.send(request, move |_, result, world| {
+                let status = match world.try_fetch_mut::<Dirty<OfflineQueue>>() {
+                    Some(mut queue) => queue.write().complete(&key, revision, &result),
+                    None => {
+                        warn!("The OfflineQueue was removed while {} was being sent.", key);
+                        return;
+                    }
+                };
+                if let Some(status) = status {
+                    if let Some(mut events) = world.try_fetch_mut::<EventChannel<DeliveryEvent>>() {
+                        events.single_write(DeliveryEvent { key, status });
+                    }
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::auto_save::AutoSaveSystem;
+    use crate::error::Error;
+    use crate::http::test::{run_next_callback, stub_server};
+    use crate::http::{HttpClientResource, HttpRequest};
+    use crate::offline_queue::*;
+
+    use amethyst::core::timing::Time;
+    use amethyst::ecs::{RunNow, World};
+    use amethyst::shrev::EventChannel;
+    use dirty::Dirty;
+    use hyper::StatusCode;
+
+    use std::fs;
+    use std::net::TcpListener;
+
+    fn queue_with(keys: &[&str]) -> OfflineQueue {
+        let mut queue = OfflineQueue::default();
+        for key in keys {
+            queue.enqueue(key, &HttpRequest::post(&format!("http://localhost/{}", key)));
+        }
+        queue
+    }
+
+    #[test]
+    fn deduplicates_by_key() {
+        let mut queue = queue_with(&["a", "b"]);
+        queue.enqueue("a", &HttpRequest::put("http://localhost/new"));
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue.requests()[0].method, "PUT");
+        assert_eq!(queue.requests()[0].url, "http://localhost/new");
+    }
+
+    #[test]
+    fn delivered_requests_are_removed() {
+        let mut queue = queue_with(&["a"]);
+        let (key, revision) = queue.ready().remove(0);
+        queue.set_in_flight(&key);
+        assert_eq!(queue.status("a"), Some(DeliveryStatus::InFlight));
+        assert!(queue.ready().is_empty());
+        assert_eq!(
+            queue.complete(&key, revision, &Ok(())),
+            Some(DeliveryStatus::Delivered)
+        );
+        assert!(queue.is_empty());
+        assert_eq!(queue.status("a"), Some(DeliveryStatus::Delivered));
+    }
+
+    #[test]
+    fn offline_probes_one_request() {
+        let mut queue = queue_with(&["a", "b", "c"]);
+        let (key, revision) = queue.ready().remove(0);
+        queue.set_in_flight(&key);
+        let result: Result<(), Error> = Err(Error::Timeout);
+        assert_eq!(queue.complete(&key, revision, &result), None);
+        assert!(!queue.online());
+        assert_eq!(queue.len(), 3);
+        assert_eq!(queue.ready().len(), 1);
+
+        let (key, revision) = queue.ready().remove(0);
+        queue.set_in_flight(&key);
+        queue.complete(&key, revision, &Ok(()));
+        assert!(queue.online());
+        assert_eq!(queue.ready().len(), 2);
+    }
+
+    #[test]
+    fn refused_requests_fail() {
+        let mut queue = queue_with(&["a"]);
+        let (key, revision) = queue.ready().remove(0);
+        queue.set_in_flight(&key);
+        let result: Result<(), Error> = Err(Error::Status(StatusCode::BAD_REQUEST));
+        assert_eq!(
+            queue.complete(&key, revision, &result),
+            Some(DeliveryStatus::Failed)
+        );
+        assert!(queue.is_empty());
+        assert!(queue.online());
+    }
+
+    #[test]
+    fn error_status_goes_back_online() {
+        let mut queue = queue_with(&["a", "b", "c"]);
+        let (key, revision) = queue.ready().remove(0);
+        queue.set_in_flight(&key);
+        let result: Result<(), Error> = Err(Error::Timeout);
+        queue.complete(&key, revision, &result);
+        assert!(!queue.online());
+
+        let (key, revision) = queue.ready().remove(0);
+        queue.set_in_flight(&key);
+        let result: Result<(), Error> = Err(Error::Status(StatusCode::NOT_FOUND));
+        assert_eq!(
+            queue.complete(&key, revision, &result),
+            Some(DeliveryStatus::Failed)
+        );
+        assert!(queue.online());
+        assert_eq!(queue.ready().len(), 2);
+
+        let (key, revision) = queue.ready().remove(0);
+        queue.set_in_flight(&key);
+        let result: Result<(), Error> = Err(Error::Status(StatusCode::SERVICE_UNAVAILABLE));
+        assert_eq!(queue.complete(&key, revision, &result), None);
+        assert!(queue.online());
+        assert_eq!(queue.status(&key), Some(DeliveryStatus::Pending));
+    }
+
+    #[test]
+    fn replaced_while_in_flight() {
+        let mut queue = queue_with(&["a"]);
+        let (key, revision) = queue.ready().remove(0);
+        queue.set_in_flight(&key);
+        queue.enqueue("a", &HttpRequest::post("http://localhost/newer"));
+        assert_eq!(queue.complete(&key, revision, &Ok(())), None);
+        assert_eq!(queue.len(), 1);
+        assert_eq!(queue.status("a"), Some(DeliveryStatus::Pending));
+    }
+
+    #[test]
+    fn replaced_while_both_in_flight() {
+        let mut queue = queue_with(&["a"]);
+        let (key, old_revision) = queue.ready().remove(0);
+        queue.set_in_flight(&key);
+        queue.enqueue("a", &HttpRequest::post("http://localhost/newer"));
+        let (key, new_revision) = queue.ready().remove(0);
+        queue.set_in_flight(&key);
+
+        // The old request completing must not make the newer one ready again.
+        assert_eq!(queue.complete(&key, old_revision, &Ok(())), None);
+        assert!(queue.ready().is_empty());
+        assert_eq!(queue.status("a"), Some(DeliveryStatus::InFlight));
+        assert_eq!(
+            queue.complete(&key, new_revision, &Ok(())),
+            Some(DeliveryStatus::Delivered)
+        );
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn system_sends_and_reports() {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        let mut world = World::new();
+        world.insert(HttpClientResource::with_sender(tx).unwrap());
+        world.insert(Time::default());
+        world.insert(EventChannel::<DeliveryEvent>::new());
+        let mut reader = world
+            .fetch_mut::<EventChannel<DeliveryEvent>>()
+            .register_reader();
+        // Nothing listens on this port.
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let mut queue = OfflineQueue::default();
+        queue.enqueue("a", &HttpRequest::post(&format!("http://127.0.0.1:{}/a", port)));
+        world.insert(Dirty::new(queue));
+        let mut system = OfflineQueueSystem::default();
+
+        // The server is down: the request stays queued.
+        system.run_now(&world);
+        run_next_callback(&rx, &mut world);
+        assert!(!world.read_resource::<Dirty<OfflineQueue>>().read().online());
+        assert_eq!(world.read_resource::<Dirty<OfflineQueue>>().read().len(), 1);
+
+        // Replaced by a request to a working server, sent by the next probe.
+        let (url, requests) = stub_server("200 OK", "");
+        world
+            .write_resource::<Dirty<OfflineQueue>>()
+            .write()
+            .enqueue("a", &HttpRequest::post(&format!("{}/b", url)));
+        system.run_now(&world);
+        assert!(requests.recv().unwrap().starts_with("POST /b HTTP/1.1"));
+        run_next_callback(&rx, &mut world);
+
+        let queue = world.read_resource::<Dirty<OfflineQueue>>();
+        assert!(queue.read().is_empty());
+        assert!(queue.read().online());
+        let events = world
+            .fetch::<EventChannel<DeliveryEvent>>()
+            .read(&mut reader)
+            .cloned()
+            .collect::<Vec<_>>();
+        assert_eq!(
+            events,
+            vec![DeliveryEvent {
+                key: "a".to_string(),
+                status: DeliveryStatus::Delivered,
+            }]
+        );
+    }
+
+    #[test]
+    fn auto_save_round_trip() {
+        let path = std::env::temp_dir().join(format!(
+            "amethyst_extra_offline_queue_{}.ron",
+            std::process::id()
+        ));
+        let path = path.to_str().unwrap().to_string();
+        let _ = fs::remove_file(&path);
+
+        let (mut system, loaded) = AutoSaveSystem::<OfflineQueue>::new(path.clone());
+        assert!(loaded.is_none());
+        let mut world = World::new();
+        let mut queue = queue_with(&["a", "b"]);
+        queue.max_attempts = 3;
+        queue.set_in_flight("a");
+        let mut dirty = Dirty::new(OfflineQueue::default());
+        *dirty.write() = queue;
+        world.insert(dirty);
+        system.run_now(&world);
+
+        let (_, loaded) = AutoSaveSystem::<OfflineQueue>::new(path.clone());
+        let loaded = loaded.expect("The queue was not saved.");
+        let loaded = loaded.read();
+        assert_eq!(loaded.max_attempts, 3);
+        assert_eq!(
+            loaded
+                .requests()
+                .iter()
+                .map(|r| (r.key.as_str(), r.url.as_str(), r.method.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("a", "http://localhost/a", "POST"),
+                ("b", "http://localhost/b", "POST"),
+            ]
+        );
+        // Requests in flight when the game stopped are sent again.
+        assert_eq!(loaded.ready().len(), 2);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,688 @@
+use crate::terminal::{exec_file, get_cvar, set_cvar, CvarRegistry};
+
+use ::amethyst::core::timing::Time;
+use ::amethyst::ecs::World;
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+
+/// The type of a command argument.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ArgType {
+    /// `true`, `false`, `1`, `0`, `on` or `off`.
+    Bool,
+    Int,
+    Float,
+    /// A single word, or a quoted string.
+    String,
+    /// Everything until the end of the line. Only valid as the last argument.
+    Text,
+}
+
+impl ArgType {
+    pub fn parse(self, raw: &str) -> Option<ArgValue> {
+        match self {
+            ArgType::Bool => match raw.to_lowercase().as_str() {
+                "true" | "1" | "on" => Some(ArgValue::Bool(true)),
+                "false" | "0" | "off" => Some(ArgValue::Bool(false)),
+                _ => None,
+            },
+            ArgType::Int => raw.parse().ok().map(ArgValue::Int),
+            ArgType::Float => raw.parse().ok().map(ArgValue::Float),
+            ArgType::String | ArgType::Text => Some(ArgValue::String(raw.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for ArgType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            ArgType::Bool => "bool",
+            ArgType::Int => "int",
+            ArgType::Float => "float",
+            ArgType::String => "string",
+            ArgType::Text => "text",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A parsed command argument.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ArgValue {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+}
+
+impl ArgValue {
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            ArgValue::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_int(&self) -> Option<i64> {
+        match self {
+            ArgValue::Int(i) => Some(*i),
+            _ => None,
+        }
+    }
+
+    /// Ints are converted to floats.
+    pub fn as_float(&self) -> Option<f64> {
+        match self {
+            ArgValue::Float(f) => Some(*f),
+            ArgValue::Int(i) => Some(*i as f64),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            ArgValue::String(s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ArgValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ArgValue::Bool(b) => write!(f, "{}", b),
+            ArgValue::Int(i) => write!(f, "{}", i),
+            ArgValue::Float(v) => write!(f, "{}", v),
+            ArgValue::String(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+/// The declaration of a command argument.
+#[derive(Debug, Clone, PartialEq, Eq, new)]
+pub struct ArgSpec {
+    pub name: String,
+    pub ty: ArgType,
+    pub optional: bool,
+}
+
+/// The arguments given to a command handler.
+/// Optional arguments that were not given are absent.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CommandArgs {
+    values: Vec<(String, ArgValue)>,
+}
+
+impl CommandArgs {
+    pub fn get(&self, name: &str) -> Option<&ArgValue> {
+        self.values.iter().find(|(n, _)| n == name).map(|(_, v)| v)
+    }
+
+    pub fn bool(&self, name: &str) -> Option<bool> {
+        self.get(name).and_then(ArgValue::as_bool)
+    }
+
+    pub fn int(&self, name: &str) -> Option<i64> {
+        self.get(name).and_then(ArgValue::as_int)
+    }
+
+    pub fn float(&self, name: &str) -> Option<f64> {
+        self.get(name).and_then(ArgValue::as_float)
+    }
+
+    pub fn str(&self, name: &str) -> Option<&str> {
+        self.get(name).and_then(ArgValue::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+}
+
+/// Runs the command. The returned text is displayed in the console, the error is displayed as such.
+pub type CommandHandler =
+    Arc<dyn Fn(&CommandArgs, &mut World) -> Result<String, String> + Send + Sync>;
+
+/// A named console command with typed arguments.
+///
+/// Usage:
+/// ```rs
+/// let command = Command::new("gravity", "Sets the gravity.")
+///     .arg("value", ArgType::Float)
+///     .handler(|args, world| {
+///         world.fetch_mut::<Gravity>().0 = args.float("value").unwrap();
+///         Ok(String::new())
+///     });
+/// world.fetch_mut::<CommandRegistry>().register(command);
+/// ```
+#[derive(Clone)]
+pub struct Command {
+    pub name: String,
+    pub help: String,
+    pub args: Vec<ArgSpec>,
+    handler: CommandHandler,
+}
+
+impl Command {
+    /// Creates a command doing nothing. Set what it does using `handler`.
+    pub fn new(name: &str, help: &str) -> Self {
+        Command {
+            name: name.to_string(),
+            help: help.to_string(),
+            args: vec![],
+            handler: Arc::new(|_, _| Ok(String::new())),
+        }
+    }
+
+    /// Adds a required argument. Required arguments must be declared before optional ones.
+    pub fn arg(mut self, name: &str, ty: ArgType) -> Self {
+        self.args.push(ArgSpec::new(name.to_string(), ty, false));
+        self
+    }
+
+    pub fn optional_arg(mut self, name: &str, ty: ArgType) -> Self {
+        self.args.push(ArgSpec::new(name.to_string(), ty, true));
+        self
+    }
+
+    pub fn handler<F>(mut self, handler: F) -> Self
+    where
+        F: Fn(&CommandArgs, &mut World) -> Result<String, String> + Send + Sync + 'static,
+    {
+        self.handler = Arc::new(handler);
+        self
+    }
+
+    /// For example "timescale <scale:float> [message:text]".
+    pub fn usage(&self) -> String {
+        let mut usage = self.name.clone();
+        for arg in &self.args {
+            if arg.optional {
+                usage.push_str(&format!(" [{}:{}]", arg.name, arg.ty));
+            } else {
+                usage.push_str(&format!(" <{}:{}>", arg.name, arg.ty));
+            }
+        }
+        usage
+    }
+
+    /// Matches the words following the command name with the declared arguments.
+    /// `rest` is the raw text following the command name, used for `ArgType::Text`.
+    pub fn parse_args(&self, words: &[String], rest: &str) -> Result<CommandArgs, CommandError> {
+        let mut values = vec![];
+        for (i, spec) in self.args.iter().enumerate() {
+            if spec.ty == ArgType::Text {
+                let text = rest_after_words(rest, i).trim();
+                if !text.is_empty() {
+                    values.push((spec.name.clone(), ArgValue::String(text.to_string())));
+                } else if !spec.optional {
+                    return Err(CommandError::MissingArgument {
+                        command: self.name.clone(),
+                        usage: self.usage(),
+                    });
+                }
+                return Ok(CommandArgs { values });
+            }
+            match words.get(i) {
+                Some(word) => {
+                    let value =
+                        spec.ty
+                            .parse(word)
+                            .ok_or_else(|| CommandError::InvalidArgument {
+                                name: spec.name.clone(),
+                                expected: spec.ty,
+                                value: word.clone(),
+                            })?;
+                    values.push((spec.name.clone(), value));
+                }
+                None if spec.optional => break,
+                None => {
+                    return Err(CommandError::MissingArgument {
+                        command: self.name.clone(),
+                        usage: self.usage(),
+                    })
+                }
+            }
+        }
+        if words.len() > self.args.len() {
+            return Err(CommandError::TooManyArguments {
+                command: self.name.clone(),
+                usage: self.usage(),
+            });
+        }
+        Ok(CommandArgs { values })
+    }
+}
+
+impl fmt::Debug for Command {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Command")
+            .field("name", &self.name)
+            .field("help", &self.help)
+            .field("args", &self.args)
+            .finish()
+    }
+}
+
+/// Errors happening when parsing or running a console command.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CommandError {
+    UnknownCommand(String),
+    MissingArgument {
+        command: String,
+        usage: String,
+    },
+    TooManyArguments {
+        command: String,
+        usage: String,
+    },
+    InvalidArgument {
+        name: String,
+        expected: ArgType,
+        value: String,
+    },
+    UnterminatedQuote,
+    /// The command ran but returned an error.
+    Failed(String),
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CommandError::UnknownCommand(c) => {
+                write!(f, "Unknown command: {}. Type help for the list.", c)
+            }
+            CommandError::MissingArgument { usage, .. } => {
+                write!(f, "Missing argument. Usage: {}", usage)
+            }
+            CommandError::TooManyArguments { usage, .. } => {
+                write!(f, "Too many arguments. Usage: {}", usage)
+            }
+            CommandError::InvalidArgument {
+                name,
+                expected,
+                value,
+            } => write!(f, "Invalid {} for {}: {}", expected, name, value),
+            CommandError::UnterminatedQuote => write!(f, "Unterminated quote"),
+            CommandError::Failed(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+/// Splits the line into words. Double quotes group words together, `\` escapes the next character.
+pub fn split_command_line(line: &str) -> Result<Vec<String>, CommandError> {
+    let (words, unterminated) = tokenize(line);
+    if unterminated {
+        return Err(CommandError::UnterminatedQuote);
+    }
+    Ok(words.into_iter().map(|(_, word)| word).collect())
+}
+
+/// The words of the line with the byte offset where each starts,
+/// and whether the line ends inside of a quote.
+fn tokenize(line: &str) -> (Vec<(usize, String)>, bool) {
+    let mut words = vec![];
+    let mut current = String::new();
+    let mut start = None;
+    let mut quoted = false;
+    let mut chars = line.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => {
+                if let Some((_, next)) = chars.next() {
+                    current.push(next);
+                }
+                start = start.or(Some(i));
+            }
+            '"' => {
+                quoted = !quoted;
+                start = start.or(Some(i));
+            }
+            c if c.is_whitespace() && !quoted => {
+                if let Some(start) = start.take() {
+                    words.push((start, current.clone()));
+                    current.clear();
+                }
+            }
+            c => {
+                current.push(c);
+                start = start.or(Some(i));
+            }
+        }
+    }
+    if let Some(start) = start {
+        words.push((start, current));
+    }
+    (words, quoted)
+}
+
+/// The raw text after the first `count` words, as split by `split_command_line`.
+fn rest_after_words(text: &str, count: usize) -> &str {
+    tokenize(text)
+        .0
+        .get(count)
+        .map(|(start, _)| &text[*start..])
+        .unwrap_or("")
+}
+
+/// The console commands, by name.
+///
+/// Run commands using `execute_command`, which gives them mutable access to the `World`.
+#[derive(Debug, Clone, Default)]
+pub struct CommandRegistry {
+    commands: BTreeMap<String, Command>,
+    revision: u64,
+}
+
+impl CommandRegistry {
+    /// Creates a registry containing the `help`, `echo`, `timescale`, `get`, `set`, `cvars` and `exec` commands.
+    pub fn with_builtins() -> Self {
+        let mut registry = CommandRegistry::default();
+        registry.register(
+            Command::new("help", "Lists the commands, or describes one.")
+                .optional_arg("command", ArgType::String)
+                .handler(|args, world| {
+                    let registry = world.fetch::<CommandRegistry>();
+                    match args.str("command") {
+                        Some(name) => registry
+                            .get(name)
+                            .map(|c| format!("{}\n  {}", c.usage(), c.help))
+                            .ok_or_else(|| format!("Unknown command: {}", name)),
+                        None => Ok(registry.help()),
+                    }
+                }),
+        );
+        registry.register(
+            Command::new("echo", "Prints the text.")
+                .optional_arg("text", ArgType::Text)
+                .handler(|args, _| Ok(args.str("text").unwrap_or("").to_string())),
+        );
+        registry.register(
+            Command::new(
+                "timescale",
+                "Sets the speed of the game, 1.0 being the normal speed. Prints it without argument.",
+            )
+            .optional_arg("scale", ArgType::Float)
+            .handler(|args, world| {
+                let mut time = world
+                    .try_fetch_mut::<Time>()
+                    .ok_or_else(|| String::from("There is no Time resource."))?;
+                match args.float("scale") {
+                    Some(scale) if scale >= 0.0 => {
+                        time.set_time_scale(scale as f32);
+                        Ok(String::new())
+                    }
+                    Some(scale) => Err(format!("The time scale can't be negative: {}", scale)),
+                    None => Ok(time.time_scale().to_string()),
+                }
+            }),
+        );
+        registry.register(
+            Command::new("get", "Prints the value of a cvar.")
+                .arg("cvar", ArgType::String)
+                .handler(|args, world| {
+                    let name = args.str("cvar").unwrap_or("");
+                    get_cvar(world, name)
+                        .map(|v| format!("{} = {}", name, v))
+                        .map_err(|e| e.to_string())
+                }),
+        );
+        registry.register(
+            Command::new("set", "Sets the value of a cvar.")
+                .arg("cvar", ArgType::String)
+                .arg("value", ArgType::Text)
+                .handler(|args, world| {
+                    set_cvar(
+                        world,
+                        args.str("cvar").unwrap_or(""),
+                        args.str("value").unwrap_or(""),
+                    )
+                    .map(|_| String::new())
+                    .map_err(|e| e.to_string())
+                }),
+        );
+        registry.register(
+            Command::new("cvars", "Lists the cvars and their values.").handler(|_, world| {
+                let cvars = world
+                    .try_fetch::<CvarRegistry>()
+                    .map(|r| r.clone())
+                    .unwrap_or_default();
+                Ok(cvars
+                    .names()
+                    .iter()
+                    .map(|name| {
+                        let value = get_cvar(world, name)
+                            .map(|v| v.to_string())
+                            .unwrap_or_else(|_| String::from("-"));
+                        format!("{} = {} - {}", name, value, cvars.get(name).unwrap().help)
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n"))
+            }),
+        );
+        registry.register(
+            Command::new("exec", "Executes a file of commands, one per line.")
+                .arg("file", ArgType::Text)
+                .handler(|args, world| {
+                    exec_file(world, args.str("file").unwrap_or("")).map_err(|errors| {
+                        errors
+                            .iter()
+                            .map(|e| e.to_string())
+                            .collect::<Vec<_>>()
+                            .join("\n")
+                    })?;
+                    Ok(String::new())
+                }),
+        );
+        registry
+    }
+
+    /// Adds the command, replacing and returning the one having the same name.
+    pub fn register(&mut self, command: Command) -> Option<Command> {
+        self.revision += 1;
+        self.commands.insert(command.name.clone(), command)
+    }
+
+    pub fn unregister(&mut self, name: &str) -> Option<Command> {
+        self.revision += 1;
+        self.commands.remove(name)
+    }
+
+    /// Changes each time a command is registered or unregistered.
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Command> {
+        self.commands.get(name)
+    }
+
+    /// The command names, sorted.
+    pub fn names(&self) -> Vec<&str> {
+        self.commands.keys().map(|k| k.as_str()).collect()
+    }
+
+    /// The command names starting with `prefix`, sorted.
+    pub fn complete(&self, prefix: &str) -> Vec<&str> {
+        self.commands
+            .keys()
+            .filter(|k| k.starts_with(prefix))
+            .map(|k| k.as_str())
+            .collect()
+    }
+
+    /// One line per command with its usage and description.
+    pub fn help(&self) -> String {
+        self.commands
+            .values()
+            .map(|c| format!("{} - {}", c.usage(), c.help))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Finds the command and parses its arguments.
+    /// Returns `None` for empty lines and comments starting with `#` or `//`.
+    pub fn parse(&self, line: &str) -> Result<Option<(CommandHandler, CommandArgs)>, CommandError> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with("//") {
+            return Ok(None);
+        }
+        let words = split_command_line(line)?;
+        let name = match words.first() {
+            Some(n) => n,
+            None => return Ok(None),
+        };
+        let command = self
+            .get(name)
+            .ok_or_else(|| CommandError::UnknownCommand(name.clone()))?;
+        let args = command.parse_args(&words[1..], rest_after_words(line, 1))?;
+        Ok(Some((command.handler.clone(), args)))
+    }
+}
+
+/// Parses and runs a command line using the `CommandRegistry` of the world.
+/// Lines starting with the name of a cvar print it, or set it if a value follows.
+/// Returns the text printed by the command.
+pub fn execute_command(world: &mut World, line: &str) -> Result<String, CommandError> {
+    // The registry is released before running the command, so that commands can use it.
+    let parsed = match world.try_fetch::<CommandRegistry>().map(|r| r.parse(line)) {
+        Some(Ok(parsed)) => parsed,
+        Some(Err(CommandError::UnknownCommand(_))) | None => {
+            let name = line.split_whitespace().next().unwrap_or("").to_string();
+            if world
+                .try_fetch::<CvarRegistry>()
+                .map(|c| c.get(&name).is_none())
+                .unwrap_or(true)
+            {
+                return Err(CommandError::UnknownCommand(name));
+            }
+            let value = rest_after_words(line, 1).trim();
+            if value.is_empty() {
+                return get_cvar(world, &name).map(|v| format!("{} = {}", name, v));
+            }
+            return set_cvar(world, &name, value).map(|_| String::new());
+        }
+        Some(Err(e)) => return Err(e),
+    };
+    match parsed {
+        Some((handler, args)) => handler(&args, world).map_err(CommandError::Failed),
+        None => Ok(String::new()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::terminal::*;
+
+    use amethyst::core::timing::Time;
+    use amethyst::ecs::World;
+
+    fn world() -> World {
+        let mut world = World::new();
+        world.insert(CommandRegistry::with_builtins());
+        world.insert(Time::default());
+        world
+    }
+
+    #[test]
+    fn split_quotes() {
+        assert_eq!(
+            split_command_line(r#"say "hello world" a\"b  c"#).unwrap(),
+            vec!["say", "hello world", "a\"b", "c"]
+        );
+        assert_eq!(
+            split_command_line(r#"say "oops"#),
+            Err(CommandError::UnterminatedQuote)
+        );
+    }
+
+    #[test]
+    fn timescale() {
+        let mut world = world();
+        assert_eq!(
+            execute_command(&mut world, "timescale 0.5"),
+            Ok(String::new())
+        );
+        assert_eq!(world.fetch::<Time>().time_scale(), 0.5);
+        assert_eq!(
+            execute_command(&mut world, "timescale"),
+            Ok(String::from("0.5"))
+        );
+        assert_eq!(
+            execute_command(&mut world, "timescale fast"),
+            Err(CommandError::InvalidArgument {
+                name: String::from("scale"),
+                expected: ArgType::Float,
+                value: String::from("fast"),
+            })
+        );
+        assert!(execute_command(&mut world, "timescale 1 2").is_err());
+    }
+
+    #[test]
+    fn custom_command_and_help() {
+        let mut world = world();
+        world.fetch_mut::<CommandRegistry>().register(
+            Command::new("give", "Gives items.")
+                .arg("item", ArgType::String)
+                .optional_arg("count", ArgType::Int)
+                .handler(|args, _| {
+                    Ok(format!(
+                        "{} x{}",
+                        args.str("item").unwrap(),
+                        args.int("count").unwrap_or(1)
+                    ))
+                }),
+        );
+        assert_eq!(
+            execute_command(&mut world, "give \"big gun\" 3"),
+            Ok(String::from("big gun x3"))
+        );
+        assert_eq!(
+            execute_command(&mut world, "give rock"),
+            Ok(String::from("rock x1"))
+        );
+        assert_eq!(
+            execute_command(&mut world, "echo  hello   there"),
+            Ok(String::from("hello   there"))
+        );
+        world.fetch_mut::<CommandRegistry>().register(
+            Command::new("tell", "Sends a message.")
+                .arg("to", ArgType::String)
+                .arg("message", ArgType::Text)
+                .handler(|args, _| {
+                    Ok(format!(
+                        "{}: {}",
+                        args.str("to").unwrap(),
+                        args.str("message").unwrap()
+                    ))
+                }),
+        );
+        assert_eq!(
+            execute_command(&mut world, "tell \"big bob\"  hello   there "),
+            Ok(String::from("big bob: hello   there"))
+        );
+        assert_eq!(
+            execute_command(&mut world, "help give"),
+            Ok(String::from(
+                "give <item:string> [count:int]\n  Gives items."
+            ))
+        );
+        assert_eq!(
+            execute_command(&mut world, "nope"),
+            Err(CommandError::UnknownCommand(String::from("nope")))
+        );
+        assert_eq!(
+            world.fetch::<CommandRegistry>().complete("t"),
+            vec!["tell", "timescale"]
+        );
+    }
+}
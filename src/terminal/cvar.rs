@@ -0,0 +1,459 @@
+use crate::auto_save::ShouldSave;
+use crate::terminal::{ArgType, ArgValue, CommandError};
+
+use ::amethyst::ecs::storage::MaskedStorage;
+use ::amethyst::ecs::*;
+use ::amethyst::shrev::EventChannel;
+
+use dirty::Dirty;
+
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::sync::Arc;
+
+/// A type that can be stored in a `Cvar`.
+pub trait CvarType: Clone + Send + Sync + 'static {
+    fn arg_type() -> ArgType;
+    fn to_value(&self) -> ArgValue;
+    fn from_value(value: &ArgValue) -> Option<Self>;
+}
+
+impl CvarType for bool {
+    fn arg_type() -> ArgType {
+        ArgType::Bool
+    }
+    fn to_value(&self) -> ArgValue {
+        ArgValue::Bool(*self)
+    }
+    fn from_value(value: &ArgValue) -> Option<Self> {
+        value.as_bool()
+    }
+}
+
+impl CvarType for i64 {
+    fn arg_type() -> ArgType {
+        ArgType::Int
+    }
+    fn to_value(&self) -> ArgValue {
+        ArgValue::Int(*self)
+    }
+    fn from_value(value: &ArgValue) -> Option<Self> {
+        value.as_int()
+    }
+}
+
+impl CvarType for i32 {
+    fn arg_type() -> ArgType {
+        ArgType::Int
+    }
+    fn to_value(&self) -> ArgValue {
+        ArgValue::Int(i64::from(*self))
+    }
+    fn from_value(value: &ArgValue) -> Option<Self> {
+        value.as_int().and_then(|i| i32::try_from(i).ok())
+    }
+}
+
+impl CvarType for u32 {
+    fn arg_type() -> ArgType {
+        ArgType::Int
+    }
+    fn to_value(&self) -> ArgValue {
+        ArgValue::Int(i64::from(*self))
+    }
+    fn from_value(value: &ArgValue) -> Option<Self> {
+        value.as_int().and_then(|i| u32::try_from(i).ok())
+    }
+}
+
+impl CvarType for f64 {
+    fn arg_type() -> ArgType {
+        ArgType::Float
+    }
+    fn to_value(&self) -> ArgValue {
+        ArgValue::Float(*self)
+    }
+    fn from_value(value: &ArgValue) -> Option<Self> {
+        value.as_float()
+    }
+}
+
+impl CvarType for f32 {
+    fn arg_type() -> ArgType {
+        ArgType::Float
+    }
+    fn to_value(&self) -> ArgValue {
+        ArgValue::Float(f64::from(*self))
+    }
+    fn from_value(value: &ArgValue) -> Option<Self> {
+        value.as_float().map(|f| f as f32)
+    }
+}
+
+impl CvarType for String {
+    fn arg_type() -> ArgType {
+        ArgType::String
+    }
+    fn to_value(&self) -> ArgValue {
+        ArgValue::String(self.clone())
+    }
+    fn from_value(value: &ArgValue) -> Option<Self> {
+        value.as_str().map(|s| s.to_string())
+    }
+}
+
+type CvarGetter = Arc<dyn Fn(&World) -> Option<ArgValue> + Send + Sync>;
+type CvarSetter = Arc<dyn Fn(&mut World, &ArgValue) -> Result<(), String> + Send + Sync>;
+
+/// A console variable, reading and writing a field of a resource or component.
+///
+/// Usage:
+/// ```rs
+/// let mut cvars = CvarRegistry::default();
+/// cvars.register(Cvar::resource(
+///     "sensitivity",
+///     "Mouse sensitivity.",
+///     |s: &MouseSensitivity| s.x,
+///     |s: &mut MouseSensitivity, v: f32| *s = MouseSensitivity::new(v, v),
+/// ).persistent());
+/// cvars.register(Cvar::component(
+///     "max_velocity_air",
+///     "Maximum air speed of the players.",
+///     |b: &BhopMovement3D| b.max_velocity_air,
+///     |b: &mut BhopMovement3D, v: f32| b.max_velocity_air = v,
+/// ));
+/// world.insert(cvars);
+/// ```
+/// Then in the console: `sensitivity 2.5`, `set max_velocity_air 20`, `cvars`.
+#[derive(Clone)]
+pub struct Cvar {
+    pub name: String,
+    pub help: String,
+    pub ty: ArgType,
+    /// Persistent cvars are saved in the `Dirty<CvarStore>` resource when it exists.
+    pub persistent: bool,
+    getter: CvarGetter,
+    setter: CvarSetter,
+}
+
+impl Cvar {
+    /// A cvar backed by a field of the resource `R`.
+    pub fn resource<R, V, G, S>(name: &str, help: &str, get: G, set: S) -> Self
+    where
+        R: Send + Sync + 'static,
+        V: CvarType,
+        G: Fn(&R) -> V + Send + Sync + 'static,
+        S: Fn(&mut R, V) + Send + Sync + 'static,
+    {
+        let cvar_name = name.to_string();
+        Cvar {
+            name: name.to_string(),
+            help: help.to_string(),
+            ty: V::arg_type(),
+            persistent: false,
+            getter: Arc::new(move |world| world.try_fetch::<R>().map(|r| get(&r).to_value())),
+            setter: Arc::new(move |world, value| {
+                let value = V::from_value(value)
+                    .ok_or_else(|| format!("Invalid {} for {}.", V::arg_type(), cvar_name))?;
+                let mut resource = world
+                    .try_fetch_mut::<R>()
+                    .ok_or_else(|| format!("{} is not available.", cvar_name))?;
+                set(&mut resource, value);
+                Ok(())
+            }),
+        }
+    }
+
+    /// A cvar backed by a field of the component `C`.
+    /// Reading gives the value of the first entity having the component,
+    /// writing changes it on all of them.
+    pub fn component<C, V, G, S>(name: &str, help: &str, get: G, set: S) -> Self
+    where
+        C: Component,
+        V: CvarType,
+        G: Fn(&C) -> V + Send + Sync + 'static,
+        S: Fn(&mut C, V) + Send + Sync + 'static,
+    {
+        let cvar_name = name.to_string();
+        Cvar {
+            name: name.to_string(),
+            help: help.to_string(),
+            ty: V::arg_type(),
+            persistent: false,
+            getter: Arc::new(move |world| {
+                if !world.has_value::<MaskedStorage<C>>() {
+                    return None;
+                }
+                let storage = world.read_storage::<C>();
+                let value = (&storage).join().next().map(|c| get(c).to_value());
+                value
+            }),
+            setter: Arc::new(move |world, value| {
+                let value = V::from_value(value)
+                    .ok_or_else(|| format!("Invalid {} for {}.", V::arg_type(), cvar_name))?;
+                if !world.has_value::<MaskedStorage<C>>() {
+                    return Err(format!("{} is not available.", cvar_name));
+                }
+                for component in (&mut world.write_storage::<C>()).join() {
+                    set(component, value.clone());
+                }
+                Ok(())
+            }),
+        }
+    }
+
+    /// Saves the value in the `Dirty<CvarStore>` resource, see `load_cvars`.
+    pub fn persistent(mut self) -> Self {
+        self.persistent = true;
+        self
+    }
+}
+
+impl fmt::Debug for Cvar {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Cvar")
+            .field("name", &self.name)
+            .field("help", &self.help)
+            .field("ty", &self.ty)
+            .field("persistent", &self.persistent)
+            .finish()
+    }
+}
+
+/// The console variables, by name.
+#[derive(Debug, Clone, Default)]
+pub struct CvarRegistry {
+    cvars: BTreeMap<String, Cvar>,
+}
+
+impl CvarRegistry {
+    /// Adds the cvar, replacing and returning the one having the same name.
+    pub fn register(&mut self, cvar: Cvar) -> Option<Cvar> {
+        self.cvars.insert(cvar.name.clone(), cvar)
+    }
+
+    pub fn unregister(&mut self, name: &str) -> Option<Cvar> {
+        self.cvars.remove(name)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Cvar> {
+        self.cvars.get(name)
+    }
+
+    /// The cvar names, sorted.
+    pub fn names(&self) -> Vec<&str> {
+        self.cvars.keys().map(|k| k.as_str()).collect()
+    }
+}
+
+/// Emitted in the `EventChannel<CvarChanged>` when a cvar is set to a different value.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CvarChanged {
+    pub name: String,
+    pub old: Option<ArgValue>,
+    pub new: ArgValue,
+}
+
+/// The saved values of the persistent cvars.
+/// Save it using an `AutoSaveSystem<CvarStore>` and apply it on startup using `load_cvars`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CvarStore {
+    pub values: BTreeMap<String, ArgValue>,
+    #[serde(skip)]
+    updated: bool,
+}
+
+impl ShouldSave for CvarStore {
+    fn save_ready(&self) -> bool {
+        self.updated
+    }
+    fn set_save_ready(&mut self, ready: bool) {
+        self.updated = ready;
+    }
+}
+
+fn find_cvar(world: &World, name: &str) -> Result<Cvar, CommandError> {
+    world
+        .try_fetch::<CvarRegistry>()
+        .and_then(|r| r.get(name).cloned())
+        .ok_or_else(|| CommandError::UnknownCommand(name.to_string()))
+}
+
+/// The current value of the cvar.
+pub fn get_cvar(world: &World, name: &str) -> Result<ArgValue, CommandError> {
+    let cvar = find_cvar(world, name)?;
+    (cvar.getter)(world).ok_or_else(|| CommandError::Failed(format!("{} is not available.", name)))
+}
+
+/// Parses the text according to the type of the cvar and sets it.
+pub fn set_cvar(world: &mut World, name: &str, raw: &str) -> Result<(), CommandError> {
+    let cvar = find_cvar(world, name)?;
+    let value = cvar
+        .ty
+        .parse(raw)
+        .ok_or_else(|| CommandError::InvalidArgument {
+            name: name.to_string(),
+            expected: cvar.ty,
+            value: raw.to_string(),
+        })?;
+    set_cvar_value(world, name, value)
+}
+
+/// Sets the cvar, emits a `CvarChanged` event if the value changed and saves persistent cvars.
+pub fn set_cvar_value(world: &mut World, name: &str, value: ArgValue) -> Result<(), CommandError> {
+    let cvar = find_cvar(world, name)?;
+    let old = (cvar.getter)(world);
+    (cvar.setter)(world, &value).map_err(CommandError::Failed)?;
+    let new = (cvar.getter)(world).unwrap_or(value);
+    if cvar.persistent {
+        if let Some(mut store) = world.try_fetch_mut::<Dirty<CvarStore>>() {
+            if store.read().values.get(name) != Some(&new) {
+                let store = store.write();
+                store.values.insert(name.to_string(), new.clone());
+                store.updated = true;
+            }
+        }
+    }
+    if old.as_ref() != Some(&new) {
+        if !world.has_value::<EventChannel<CvarChanged>>() {
+            world.insert(EventChannel::<CvarChanged>::new());
+        }
+        world
+            .fetch_mut::<EventChannel<CvarChanged>>()
+            .single_write(CvarChanged {
+                name: name.to_string(),
+                old,
+                new,
+            });
+    }
+    Ok(())
+}
+
+/// Applies the values saved in the `Dirty<CvarStore>` resource to the persistent cvars.
+/// Call it once the cvars are registered and the resources they use exist.
+pub fn load_cvars(world: &mut World) {
+    let values = match world.try_fetch::<Dirty<CvarStore>>() {
+        Some(store) => store.read().values.clone(),
+        None => return,
+    };
+    for (name, value) in values {
+        let persistent = find_cvar(world, &name)
+            .map(|c| c.persistent)
+            .unwrap_or(false);
+        if !persistent {
+            continue;
+        }
+        if let Err(e) = set_cvar_value(world, &name, value) {
+            warn!("Failed to load the saved value of cvar {}: {}", name, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::terminal::*;
+    use crate::{BhopMovement3D, MouseSensitivity};
+
+    use amethyst::ecs::{Builder, Join, World, WorldExt};
+    use amethyst::shrev::EventChannel;
+    use dirty::Dirty;
+
+    fn world() -> World {
+        let mut world = World::new();
+        world.register::<BhopMovement3D>();
+        world.insert(CommandRegistry::with_builtins());
+        world.insert(MouseSensitivity::new(1.0, 1.0));
+        world.insert(Dirty::new(CvarStore::default()));
+        let mut cvars = CvarRegistry::default();
+        cvars.register(
+            Cvar::resource(
+                "sensitivity",
+                "Mouse sensitivity.",
+                |s: &MouseSensitivity| s.x,
+                |s: &mut MouseSensitivity, v: f32| *s = MouseSensitivity::new(v, v),
+            )
+            .persistent(),
+        );
+        cvars.register(Cvar::component(
+            "max_velocity_air",
+            "Maximum air speed.",
+            |b: &BhopMovement3D| b.max_velocity_air,
+            |b: &mut BhopMovement3D, v: f32| b.max_velocity_air = v,
+        ));
+        world.insert(cvars);
+        world
+    }
+
+    #[test]
+    fn resource_cvar() {
+        let mut world = world();
+        let mut reader = world
+            .entry::<EventChannel<CvarChanged>>()
+            .or_insert_with(EventChannel::new)
+            .register_reader();
+        assert_eq!(
+            execute_command(&mut world, "sensitivity 2.5"),
+            Ok(String::new())
+        );
+        assert_eq!(world.fetch::<MouseSensitivity>().y, 2.5);
+        assert_eq!(
+            execute_command(&mut world, "get sensitivity"),
+            Ok(String::from("sensitivity = 2.5"))
+        );
+        assert_eq!(
+            execute_command(&mut world, "sensitivity"),
+            Ok(String::from("sensitivity = 2.5"))
+        );
+        assert!(execute_command(&mut world, "set sensitivity high").is_err());
+
+        let events = world
+            .fetch::<EventChannel<CvarChanged>>()
+            .read(&mut reader)
+            .cloned()
+            .collect::<Vec<_>>();
+        assert_eq!(
+            events,
+            vec![CvarChanged {
+                name: String::from("sensitivity"),
+                old: Some(ArgValue::Float(1.0)),
+                new: ArgValue::Float(2.5),
+            }]
+        );
+        assert!(world.fetch::<Dirty<CvarStore>>().dirty());
+        assert_eq!(
+            world
+                .fetch::<Dirty<CvarStore>>()
+                .read()
+                .values
+                .get("sensitivity"),
+            Some(&ArgValue::Float(2.5))
+        );
+    }
+
+    #[test]
+    fn component_cvar_and_load() {
+        let mut world = world();
+        for _ in 0..2 {
+            world
+                .create_entity()
+                .with(BhopMovement3D::new(false, 1.0, 1.0, 10.0, 10.0, true))
+                .build();
+        }
+        set_cvar(&mut world, "max_velocity_air", "20").unwrap();
+        let storage = world.read_storage::<BhopMovement3D>();
+        assert!((&storage).join().all(|b| b.max_velocity_air == 20.0));
+        drop(storage);
+
+        world
+            .fetch_mut::<Dirty<CvarStore>>()
+            .write()
+            .values
+            .insert(String::from("sensitivity"), ArgValue::Float(0.5));
+        load_cvars(&mut world);
+        assert_eq!(world.fetch::<MouseSensitivity>().x, 0.5);
+        assert!(execute_command(&mut world, "cvars")
+            .unwrap()
+            .contains("max_velocity_air = 20"));
+    }
+}
@@ -0,0 +1,204 @@
+use crate::http::WorldCallback;
+use crate::terminal::{execute_command, CommandRegistry, Key, KeyDecoder, LineEditor, CROSSTERM};
+
+use ::amethyst::ecs::{Read, System};
+use ::amethyst::CallbackQueue;
+use crossbeam_channel::Sender;
+use crossterm::terminal::ClearType;
+
+use std::io::Read as IORead;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, sleep, JoinHandle};
+use std::time::Duration;
+
+/// Prints text above the input line of the `TerminalConsole`, keeping the input line at the bottom.
+#[derive(Clone)]
+pub struct TerminalPrinter {
+    editor: Arc<Mutex<LineEditor>>,
+}
+
+impl TerminalPrinter {
+    pub fn print(&self, msg: &str) {
+        if let Ok(editor) = self.editor.lock() {
+            swap_write(msg, &editor);
+        }
+    }
+
+    /// Redraws the input line.
+    pub fn refresh(&self) {
+        if let Ok(editor) = self.editor.lock() {
+            refresh_input_line(&editor);
+        }
+    }
+}
+
+/// Writes the message where the input line was, then redraws the input line below it.
+fn swap_write(msg: &str, editor: &LineEditor) {
+    let terminal = CROSSTERM.terminal();
+    let cursor = CROSSTERM.cursor();
+    let (_, term_height) = terminal.terminal_size();
+    cursor.goto(0, term_height);
+    terminal.clear(ClearType::CurrentLine);
+    // The terminal is in raw mode, new lines don't return to the start of the line.
+    terminal.write(format!("{}\r\n", msg.replace('\n', "\r\n")));
+    refresh_input_line(editor);
+}
+
+fn refresh_input_line(editor: &LineEditor) {
+    let terminal = CROSSTERM.terminal();
+    let cursor = CROSSTERM.cursor();
+    let (_, term_height) = terminal.terminal_size();
+    cursor.goto(0, term_height);
+    terminal.clear(ClearType::CurrentLine);
+    terminal.write(format!(">{}", editor.line()));
+    cursor.goto(1 + editor.cursor() as u16, term_height);
+}
+
+/// A console reading commands from the terminal the game was started from.
+///
+/// A background thread reads the keys and edits the input line, which supports cursor movement,
+/// history (up/down) and command name completion (tab).
+/// Submitted lines are executed on the main thread through the `CallbackQueue`, using `execute_command`.
+/// Add the `TerminalConsoleSystem` to keep the completion up to date with the `CommandRegistry`.
+///
+/// Ctrl+C exits the game, since the terminal is in raw mode.
+///
+/// Usage:
+/// ```rs
+/// world.insert(CommandRegistry::with_builtins());
+/// let console = TerminalConsole::new(&world.read_resource::<CallbackQueue>());
+/// world.insert(console);
+/// ```
+pub struct TerminalConsole {
+    editor: Arc<Mutex<LineEditor>>,
+    completions: Arc<Mutex<Vec<String>>>,
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl TerminalConsole {
+    pub fn new(callback_queue: &CallbackQueue) -> Self {
+        Self::with_sender(callback_queue.send_handle())
+    }
+
+    /// Sends the commands to the provided channel instead of the `CallbackQueue`.
+    pub fn with_sender(sender: Sender<WorldCallback>) -> Self {
+        let editor = Arc::new(Mutex::new(LineEditor::default()));
+        let completions = Arc::new(Mutex::new(vec![]));
+        let running = Arc::new(AtomicBool::new(true));
+
+        let printer = TerminalPrinter {
+            editor: editor.clone(),
+        };
+        let thread_completions = completions.clone();
+        let thread_running = running.clone();
+        let thread = thread::spawn(move || {
+            let mut input = CROSSTERM.input().read_async().bytes();
+            let mut decoder = KeyDecoder::default();
+            printer.refresh();
+            while thread_running.load(Ordering::SeqCst) {
+                while let Some(Ok(b)) = input.next() {
+                    let key = match decoder.feed(b) {
+                        Some(k) => k,
+                        None => continue,
+                    };
+                    if key == Key::CtrlC {
+                        CROSSTERM.terminal().exit();
+                        return;
+                    }
+                    let mut editor = match printer.editor.lock() {
+                        Ok(e) => e,
+                        Err(_) => return,
+                    };
+                    if key == Key::Tab {
+                        let names = thread_completions
+                            .lock()
+                            .map(|c| c.clone())
+                            .unwrap_or_default();
+                        let candidates = editor.complete(&names);
+                        if !candidates.is_empty() {
+                            swap_write(&candidates.join("  "), &editor);
+                        }
+                    } else if let Some(line) = editor.handle_key(key) {
+                        swap_write(&format!(">{}", line), &editor);
+                        let printer = printer.clone();
+                        let callback: WorldCallback =
+                            Box::new(move |world| match execute_command(world, &line) {
+                                Ok(ref out) if out.is_empty() => {}
+                                Ok(out) => printer.print(&out),
+                                Err(e) => printer.print(&e.to_string()),
+                            });
+                        if sender.send(callback).is_err() {
+                            // The logger prints through the editor, release it first.
+                            drop(editor);
+                            error!("The game stopped receiving terminal commands.");
+                            return;
+                        }
+                    }
+                    refresh_input_line(&editor);
+                }
+                sleep(Duration::from_millis(10));
+            }
+        });
+
+        TerminalConsole {
+            editor,
+            completions,
+            running,
+            thread: Some(thread),
+        }
+    }
+
+    pub fn printer(&self) -> TerminalPrinter {
+        TerminalPrinter {
+            editor: self.editor.clone(),
+        }
+    }
+
+    /// Sets the names completed when pressing tab.
+    pub fn set_completions(&self, names: Vec<String>) {
+        if let Ok(mut completions) = self.completions.lock() {
+            *completions = names;
+        }
+    }
+
+    /// The submitted lines, oldest first.
+    pub fn history(&self) -> Vec<String> {
+        self.editor
+            .lock()
+            .map(|e| e.history().to_vec())
+            .unwrap_or_default()
+    }
+}
+
+impl Drop for TerminalConsole {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Keeps the command completion of the `TerminalConsole` in sync with the `CommandRegistry`.
+#[derive(Default)]
+pub struct TerminalConsoleSystem {
+    revision: Option<u64>,
+}
+
+impl<'a> System<'a> for TerminalConsoleSystem {
+    type SystemData = (
+        Option<Read<'a, TerminalConsole>>,
+        Option<Read<'a, CommandRegistry>>,
+    );
+
+    fn run(&mut self, (console, registry): Self::SystemData) {
+        if let (Some(console), Some(registry)) = (console, registry) {
+            if self.revision != Some(registry.revision()) {
+                console.set_completions(registry.names().iter().map(|n| n.to_string()).collect());
+                self.revision = Some(registry.revision());
+            }
+        }
+    }
+}
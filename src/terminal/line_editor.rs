@@ -0,0 +1,395 @@
+use std::str;
+
+/// A key press read from the terminal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Char(char),
+    Enter,
+    Tab,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    CtrlC,
+}
+
+/// Turns the bytes read from the terminal into `Key`s, including utf8 characters and escape sequences.
+#[derive(Debug, Clone, Default)]
+pub struct KeyDecoder {
+    pending: Vec<u8>,
+}
+
+impl KeyDecoder {
+    /// Returns a key once a full sequence is read. Unknown sequences are ignored.
+    pub fn feed(&mut self, byte: u8) -> Option<Key> {
+        if self.pending.is_empty() {
+            return match byte {
+                3 => Some(Key::CtrlC),
+                9 => Some(Key::Tab),
+                b'\n' | b'\r' => Some(Key::Enter),
+                8 | 127 => Some(Key::Backspace),
+                27 | 0x80..=0xff => {
+                    self.pending.push(byte);
+                    None
+                }
+                0..=31 => None,
+                _ => Some(Key::Char(byte as char)),
+            };
+        }
+
+        if self.pending == [27] && byte != b'[' && byte != b'O' {
+            // A lone escape, the byte starts the next key.
+            self.pending.clear();
+            return self.feed(byte);
+        }
+
+        self.pending.push(byte);
+        if self.pending[0] != 27 {
+            // Utf8 character.
+            return match str::from_utf8(&self.pending) {
+                Ok(s) => {
+                    let c = s.chars().next();
+                    self.pending.clear();
+                    c.map(Key::Char)
+                }
+                Err(_) if self.pending.len() >= 4 => {
+                    self.pending.clear();
+                    None
+                }
+                Err(_) => None,
+            };
+        }
+
+        let sequence = &self.pending[1..];
+        let last = sequence[sequence.len() - 1];
+        if sequence.len() == 1 && (last == b'[' || last == b'O') {
+            return None;
+        }
+        if sequence[0] == b'[' && (last.is_ascii_digit() || last == b';') && sequence.len() < 8 {
+            // The sequence continues.
+            return None;
+        }
+        let key = if sequence.len() == 2 && (sequence[0] == b'[' || sequence[0] == b'O') {
+            match last {
+                b'A' => Some(Key::Up),
+                b'B' => Some(Key::Down),
+                b'C' => Some(Key::Right),
+                b'D' => Some(Key::Left),
+                b'H' => Some(Key::Home),
+                b'F' => Some(Key::End),
+                _ => None,
+            }
+        } else if sequence[0] == b'[' && last == b'~' {
+            match &sequence[1..sequence.len() - 1] {
+                b"1" | b"7" => Some(Key::Home),
+                b"3" => Some(Key::Delete),
+                b"4" | b"8" => Some(Key::End),
+                _ => None,
+            }
+        } else {
+            None
+        };
+        self.pending.clear();
+        key
+    }
+}
+
+/// The input line of the terminal console, with history and command completion.
+#[derive(Debug, Clone)]
+pub struct LineEditor {
+    buffer: Vec<char>,
+    cursor: usize,
+    history: Vec<String>,
+    /// The history entry being displayed, if any.
+    history_index: Option<usize>,
+    /// The line being typed before browsing the history.
+    saved: String,
+    pub max_history: usize,
+}
+
+impl Default for LineEditor {
+    fn default() -> Self {
+        LineEditor {
+            buffer: vec![],
+            cursor: 0,
+            history: vec![],
+            history_index: None,
+            saved: String::new(),
+            max_history: 100,
+        }
+    }
+}
+
+impl LineEditor {
+    pub fn line(&self) -> String {
+        self.buffer.iter().collect()
+    }
+
+    /// The position of the cursor, in characters.
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    pub fn history(&self) -> &[String] {
+        &self.history
+    }
+
+    /// Replaces the line and moves the cursor to the end.
+    pub fn set_line(&mut self, line: &str) {
+        self.buffer = line.chars().collect();
+        self.cursor = self.buffer.len();
+    }
+
+    pub fn insert(&mut self, c: char) {
+        self.buffer.insert(self.cursor, c);
+        self.cursor += 1;
+    }
+
+    pub fn backspace(&mut self) {
+        if self.cursor > 0 {
+            self.cursor -= 1;
+            self.buffer.remove(self.cursor);
+        }
+    }
+
+    pub fn delete(&mut self) {
+        if self.cursor < self.buffer.len() {
+            self.buffer.remove(self.cursor);
+        }
+    }
+
+    pub fn left(&mut self) {
+        self.cursor = self.cursor.saturating_sub(1);
+    }
+
+    pub fn right(&mut self) {
+        self.cursor = (self.cursor + 1).min(self.buffer.len());
+    }
+
+    pub fn home(&mut self) {
+        self.cursor = 0;
+    }
+
+    pub fn end(&mut self) {
+        self.cursor = self.buffer.len();
+    }
+
+    /// Shows the previous history entry.
+    pub fn history_prev(&mut self) {
+        if self.history.is_empty() {
+            return;
+        }
+        let index = match self.history_index {
+            Some(i) => i.saturating_sub(1),
+            None => {
+                self.saved = self.line();
+                self.history.len() - 1
+            }
+        };
+        self.history_index = Some(index);
+        let line = self.history[index].clone();
+        self.set_line(&line);
+    }
+
+    /// Shows the next history entry, or the line being typed after the last one.
+    pub fn history_next(&mut self) {
+        if let Some(i) = self.history_index {
+            if i + 1 < self.history.len() {
+                self.history_index = Some(i + 1);
+                let line = self.history[i + 1].clone();
+                self.set_line(&line);
+            } else {
+                self.history_index = None;
+                let line = self.saved.clone();
+                self.set_line(&line);
+            }
+        }
+    }
+
+    /// Clears the line and returns it. Non empty lines are added to the history.
+    pub fn submit(&mut self) -> String {
+        let line = self.line();
+        self.buffer.clear();
+        self.cursor = 0;
+        self.history_index = None;
+        self.saved.clear();
+        if !line.trim().is_empty() && self.history.last() != Some(&line) {
+            self.history.push(line.clone());
+            if self.history.len() > self.max_history {
+                self.history.remove(0);
+            }
+        }
+        line
+    }
+
+    /// Completes the command name under the cursor.
+    /// Completes as much as possible and returns the candidates if there are more than one.
+    pub fn complete(&mut self, names: &[String]) -> Vec<String> {
+        let line = self.line();
+        let start = line.len() - line.trim_start().len();
+        let word_end = line[start..]
+            .find(char::is_whitespace)
+            .map(|i| start + i)
+            .unwrap_or_else(|| line.len());
+        // Only the first word is a command name.
+        if self.cursor != line[..word_end].chars().count() {
+            return vec![];
+        }
+        let word = &line[start..word_end];
+        let candidates = names
+            .iter()
+            .filter(|n| n.starts_with(word))
+            .cloned()
+            .collect::<Vec<_>>();
+        let completed = match candidates.len() {
+            0 => return vec![],
+            1 => format!("{} ", candidates[0]),
+            _ => common_prefix(&candidates),
+        };
+        if completed.len() > word.len() {
+            // The completed name already ends with a space when it is unique.
+            let rest = if completed.ends_with(' ') {
+                line[word_end..].trim_start()
+            } else {
+                &line[word_end..]
+            };
+            let new_line = format!("{}{}", &line[..start], completed);
+            self.set_line(&new_line);
+            self.buffer.extend(rest.chars());
+        }
+        if candidates.len() > 1 {
+            candidates
+        } else {
+            vec![]
+        }
+    }
+
+    /// Applies the key to the line. Returns the submitted line on `Key::Enter`.
+    /// Tab and Ctrl+C are left to the caller.
+    pub fn handle_key(&mut self, key: Key) -> Option<String> {
+        match key {
+            Key::Char(c) => self.insert(c),
+            Key::Backspace => self.backspace(),
+            Key::Delete => self.delete(),
+            Key::Left => self.left(),
+            Key::Right => self.right(),
+            Key::Up => self.history_prev(),
+            Key::Down => self.history_next(),
+            Key::Home => self.home(),
+            Key::End => self.end(),
+            Key::Enter => return Some(self.submit()),
+            Key::Tab | Key::CtrlC => {}
+        }
+        None
+    }
+}
+
+fn common_prefix(words: &[String]) -> String {
+    let first = match words.first() {
+        Some(f) => f,
+        None => return String::new(),
+    };
+    let mut len = first.len();
+    for word in &words[1..] {
+        len = first
+            .char_indices()
+            .zip(word.chars())
+            .take_while(|((_, a), b)| a == b)
+            .last()
+            .map(|((i, a), _)| i + a.len_utf8())
+            .unwrap_or(0)
+            .min(len);
+    }
+    first[..len].to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::terminal::*;
+
+    fn decode(bytes: &[u8]) -> Vec<Key> {
+        let mut decoder = KeyDecoder::default();
+        bytes.iter().filter_map(|b| decoder.feed(*b)).collect()
+    }
+
+    #[test]
+    fn decode_keys() {
+        assert_eq!(
+            decode(b"a\x1b[A\x1b[D\x1b[3~\x7f\r\t"),
+            vec![
+                Key::Char('a'),
+                Key::Up,
+                Key::Left,
+                Key::Delete,
+                Key::Backspace,
+                Key::Enter,
+                Key::Tab
+            ]
+        );
+        assert_eq!(decode("é".as_bytes()), vec![Key::Char('é')]);
+        assert_eq!(decode(b"\x1bb\x1b\x1b[B"), vec![Key::Char('b'), Key::Down]);
+    }
+
+    #[test]
+    fn edit_and_history() {
+        let mut editor = LineEditor::default();
+        for c in "timscale".chars() {
+            editor.handle_key(Key::Char(c));
+        }
+        editor.home();
+        editor.right();
+        editor.right();
+        editor.right();
+        editor.insert('e');
+        assert_eq!(
+            editor.handle_key(Key::Enter),
+            Some(String::from("timescale"))
+        );
+        editor.set_line("echo hi");
+        editor.submit();
+
+        editor.set_line("unfinished");
+        editor.handle_key(Key::Up);
+        assert_eq!(editor.line(), "echo hi");
+        editor.handle_key(Key::Up);
+        editor.handle_key(Key::Up);
+        assert_eq!(editor.line(), "timescale");
+        editor.handle_key(Key::Down);
+        editor.handle_key(Key::Down);
+        assert_eq!(editor.line(), "unfinished");
+    }
+
+    #[test]
+    fn completion() {
+        let names = vec![
+            String::from("timescale"),
+            String::from("timer"),
+            String::from("help"),
+        ];
+        let mut editor = LineEditor::default();
+        editor.set_line("t");
+        assert_eq!(editor.complete(&names), vec!["timescale", "timer"]);
+        assert_eq!(editor.line(), "time");
+        editor.set_line("he");
+        assert!(editor.complete(&names).is_empty());
+        assert_eq!(editor.line(), "help ");
+        assert_eq!(editor.cursor(), 5);
+
+        editor.set_line("t foo");
+        editor.home();
+        editor.right();
+        editor.complete(&names);
+        assert_eq!(editor.line(), "time foo");
+        assert_eq!(editor.cursor(), 4);
+        editor.set_line("ti  foo");
+        editor.home();
+        editor.right();
+        editor.right();
+        editor.complete(&names[..1]);
+        assert_eq!(editor.line(), "timescale foo");
+    }
+}
@@ -0,0 +1,407 @@
+use crate::terminal::TerminalPrinter;
+
+use fern::colors::ColoredLevelConfig;
+use log::{Level, LevelFilter};
+
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// A line logged through the `ConsoleLogger`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogLine {
+    pub level: Level,
+    pub target: String,
+    pub message: String,
+}
+
+/// The most recent log lines, for in-game consoles.
+/// Clones share the same lines.
+#[derive(Debug, Clone)]
+pub struct LogBuffer {
+    inner: Arc<Mutex<LogBufferInner>>,
+}
+
+#[derive(Debug)]
+struct LogBufferInner {
+    lines: VecDeque<LogLine>,
+    capacity: usize,
+    /// The total number of lines pushed.
+    count: u64,
+}
+
+impl LogBuffer {
+    pub fn new(capacity: usize) -> Self {
+        LogBuffer {
+            inner: Arc::new(Mutex::new(LogBufferInner {
+                lines: VecDeque::with_capacity(capacity),
+                capacity,
+                count: 0,
+            })),
+        }
+    }
+
+    /// Adds a line, dropping the oldest one if the buffer is full.
+    pub fn push(&self, line: LogLine) {
+        if let Ok(mut inner) = self.inner.lock() {
+            if inner.capacity == 0 {
+                return;
+            }
+            if inner.lines.len() == inner.capacity {
+                inner.lines.pop_front();
+            }
+            inner.lines.push_back(line);
+            inner.count += 1;
+        }
+    }
+
+    /// The lines in the buffer, oldest first.
+    pub fn lines(&self) -> Vec<LogLine> {
+        self.inner
+            .lock()
+            .map(|inner| inner.lines.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// The `count` most recent lines, oldest first.
+    pub fn recent(&self, count: usize) -> Vec<LogLine> {
+        self.inner
+            .lock()
+            .map(|inner| {
+                let skip = inner.lines.len().saturating_sub(count);
+                inner.lines.iter().skip(skip).cloned().collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// The total number of lines pushed. Changes each time a line is added.
+    pub fn count(&self) -> u64 {
+        self.inner.lock().map(|inner| inner.count).unwrap_or(0)
+    }
+
+    pub fn clear(&self) {
+        if let Ok(mut inner) = self.inner.lock() {
+            inner.lines.clear();
+        }
+    }
+}
+
+impl Default for LogBuffer {
+    fn default() -> Self {
+        LogBuffer::new(500)
+    }
+}
+
+/// A log file that is renamed to `name.1` once it reaches `max_size` bytes.
+/// The previous `name.1` becomes `name.2` and so on, up to `max_files` old files.
+pub struct RotatingFile {
+    path: PathBuf,
+    max_size: u64,
+    max_files: usize,
+    file: File,
+    size: u64,
+    /// The last byte written ended a line. Rotating only then keeps each record in one file.
+    line_start: bool,
+}
+
+impl RotatingFile {
+    pub fn new<P: AsRef<Path>>(path: P, max_size: u64, max_files: usize) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        if let Some(parent) = path.parent() {
+            if !parent.as_os_str().is_empty() {
+                fs::create_dir_all(parent)?;
+            }
+        }
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(RotatingFile {
+            path,
+            max_size,
+            max_files,
+            file,
+            size,
+            line_start: true,
+        })
+    }
+
+    fn numbered(&self, n: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", n));
+        PathBuf::from(name)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.max_files == 0 {
+            self.file = File::create(&self.path)?;
+        } else {
+            for n in (1..self.max_files).rev() {
+                let from = self.numbered(n);
+                if from.exists() {
+                    fs::rename(&from, self.numbered(n + 1))?;
+                }
+            }
+            fs::rename(&self.path, self.numbered(1))?;
+            self.file = File::create(&self.path)?;
+        }
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.line_start && self.size > 0 && self.size + buf.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        if written > 0 {
+            self.line_start = buf[written - 1] == b'\n';
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Sets up the global logger.
+///
+/// Logs go to the terminal with colors, optionally to a rotating file, and to a `LogBuffer`
+/// that in-game consoles can display.
+/// When a `TerminalPrinter` is given, the terminal output is printed above the `TerminalConsole` input line.
+///
+/// Usage:
+/// ```rs
+/// let log_buffer = ConsoleLogger::new(LevelFilter::Info)
+///     .level_for("gfx_backend_vulkan", LevelFilter::Warn)
+///     .file("logs/game.log", 1024 * 1024, 3)
+///     .terminal(world.read_resource::<TerminalConsole>().printer())
+///     .start()?;
+/// world.insert(log_buffer);
+/// ```
+pub struct ConsoleLogger {
+    level: LevelFilter,
+    module_levels: Vec<(String, LevelFilter)>,
+    colored: bool,
+    file: Option<(PathBuf, u64, usize)>,
+    buffer: LogBuffer,
+    printer: Option<TerminalPrinter>,
+}
+
+impl ConsoleLogger {
+    pub fn new(level: LevelFilter) -> Self {
+        ConsoleLogger {
+            level,
+            module_levels: vec![],
+            colored: true,
+            file: None,
+            buffer: LogBuffer::default(),
+            printer: None,
+        }
+    }
+
+    /// Overrides the level for a module and its submodules, for example "amethyst_assets".
+    pub fn level_for(mut self, module: &str, level: LevelFilter) -> Self {
+        self.module_levels.push((module.to_string(), level));
+        self
+    }
+
+    /// Enables or disables the colors of the terminal output.
+    pub fn colored(mut self, colored: bool) -> Self {
+        self.colored = colored;
+        self
+    }
+
+    /// Also writes the logs to a file, rotated when it reaches `max_size` bytes.
+    pub fn file<P: AsRef<Path>>(mut self, path: P, max_size: u64, max_files: usize) -> Self {
+        self.file = Some((path.as_ref().to_path_buf(), max_size, max_files));
+        self
+    }
+
+    /// The number of lines kept in the `LogBuffer`. Defaults to 500.
+    pub fn buffer_capacity(mut self, capacity: usize) -> Self {
+        self.buffer = LogBuffer::new(capacity);
+        self
+    }
+
+    /// Prints the terminal output above the input line of a `TerminalConsole`.
+    pub fn terminal(mut self, printer: TerminalPrinter) -> Self {
+        self.printer = Some(printer);
+        self
+    }
+
+    /// Creates the logger without setting it as the global logger.
+    /// Returns the max level and the logger, to be given to `log::set_boxed_logger`.
+    pub fn build(self) -> Result<(LevelFilter, Box<dyn log::Log>, LogBuffer), io::Error> {
+        let mut root = fern::Dispatch::new().level(self.level);
+        for (module, level) in self.module_levels {
+            root = root.level_for(module, level);
+        }
+
+        let colors = ColoredLevelConfig::new();
+        let colored = self.colored;
+        let mut terminal = fern::Dispatch::new().format(move |out, message, record| {
+            if colored {
+                out.finish(format_args!(
+                    "\x1B[{color}m[{level}][{target}] {message}\x1B[0m",
+                    color = colors.get_color(&record.level()).to_fg_str(),
+                    level = record.level(),
+                    target = record.target(),
+                    message = message,
+                ))
+            } else {
+                out.finish(format_args!(
+                    "[{}][{}] {}",
+                    record.level(),
+                    record.target(),
+                    message
+                ))
+            }
+        });
+        terminal = match self.printer {
+            Some(printer) => terminal.chain(fern::Output::call(move |record| {
+                printer.print(&record.args().to_string())
+            })),
+            None => terminal.chain(io::stdout()),
+        };
+        root = root.chain(terminal);
+
+        if let Some((path, max_size, max_files)) = self.file {
+            let file = RotatingFile::new(path, max_size, max_files)?;
+            root = root.chain(
+                fern::Dispatch::new()
+                    .format(|out, message, record| {
+                        out.finish(format_args!(
+                            "[{}][{}] {}",
+                            record.level(),
+                            record.target(),
+                            message
+                        ))
+                    })
+                    .chain(fern::Output::writer(
+                        Box::new(file) as Box<dyn Write + Send>,
+                        "\n",
+                    )),
+            );
+        }
+
+        let buffer = self.buffer.clone();
+        root = root.chain(fern::Output::call(move |record| {
+            buffer.push(LogLine {
+                level: record.level(),
+                target: record.target().to_string(),
+                message: record.args().to_string(),
+            })
+        }));
+
+        let (level, logger) = root.into_log();
+        Ok((level, logger, self.buffer))
+    }
+
+    /// Sets the global logger. Returns the `LogBuffer` receiving the log lines.
+    pub fn start(self) -> Result<LogBuffer, fern::InitError> {
+        let (level, logger, buffer) = self.build()?;
+        log::set_boxed_logger(logger)?;
+        log::set_max_level(level);
+        Ok(buffer)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::terminal::*;
+
+    use log::{Level, LevelFilter, Log, Metadata, Record};
+
+    use std::fs;
+    use std::io::Write;
+
+    fn log(logger: &dyn Log, level: Level, target: &str, message: &str) {
+        let metadata = Metadata::builder().level(level).target(target).build();
+        if logger.enabled(&metadata) {
+            logger.log(
+                &Record::builder()
+                    .metadata(metadata)
+                    .args(format_args!("{}", message))
+                    .build(),
+            );
+        }
+    }
+
+    #[test]
+    fn module_levels_and_buffer() {
+        let (_, logger, buffer) = ConsoleLogger::new(LevelFilter::Info)
+            .level_for("noisy", LevelFilter::Error)
+            .colored(false)
+            .buffer_capacity(2)
+            .build()
+            .unwrap();
+        log(&*logger, Level::Info, "game", "one");
+        log(&*logger, Level::Warn, "noisy::module", "hidden");
+        log(&*logger, Level::Debug, "game", "hidden");
+        log(&*logger, Level::Error, "noisy", "two");
+        log(&*logger, Level::Info, "game", "three");
+
+        let messages = buffer
+            .lines()
+            .into_iter()
+            .map(|l| l.message)
+            .collect::<Vec<_>>();
+        assert_eq!(messages, vec!["two", "three"]);
+        assert_eq!(buffer.count(), 3);
+        assert_eq!(buffer.recent(1)[0].target, "game");
+    }
+
+    #[test]
+    fn file_rotation() {
+        let dir = std::env::temp_dir().join(format!("amethyst_extra_logs_{}", std::process::id()));
+        let path = dir.join("game.log");
+        let _ = fs::remove_dir_all(&dir);
+        {
+            let mut file = RotatingFile::new(&path, 10, 2).unwrap();
+            for line in &["aaaaaaaa\n", "bbbbbbbb\n", "cccccccc\n", "dddddddd\n"] {
+                file.write_all(line.as_bytes()).unwrap();
+            }
+            file.flush().unwrap();
+        }
+        assert_eq!(fs::read_to_string(&path).unwrap(), "dddddddd\n");
+        assert_eq!(
+            fs::read_to_string(dir.join("game.log.1")).unwrap(),
+            "cccccccc\n"
+        );
+        assert_eq!(
+            fs::read_to_string(dir.join("game.log.2")).unwrap(),
+            "bbbbbbbb\n"
+        );
+        assert!(!dir.join("game.log.3").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rotation_keeps_records_whole() {
+        let dir =
+            std::env::temp_dir().join(format!("amethyst_extra_split_logs_{}", std::process::id()));
+        let path = dir.join("game.log");
+        let _ = fs::remove_dir_all(&dir);
+        {
+            // Like fern, the message and the line ending are written separately.
+            let mut file = RotatingFile::new(&path, 10, 1).unwrap();
+            for message in &["aaaa", "bbbbb", "cc"] {
+                file.write_all(message.as_bytes()).unwrap();
+                file.write_all(b"\n").unwrap();
+            }
+            file.flush().unwrap();
+        }
+        assert_eq!(fs::read_to_string(&path).unwrap(), "cc\n");
+        assert_eq!(
+            fs::read_to_string(dir.join("game.log.1")).unwrap(),
+            "aaaa\nbbbbb\n"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
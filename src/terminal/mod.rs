@@ -0,0 +1,77 @@
+mod command;
+mod cvar;
+mod frontend;
+mod line_editor;
+mod logger;
+mod remote;
+mod script;
+mod ui_console;
+
+pub use self::command::*;
+pub use self::cvar::*;
+pub use self::frontend::*;
+pub use self::line_editor::*;
+pub use self::logger::*;
+pub use self::remote::*;
+pub use self::script::*;
+pub use self::ui_console::*;
+
+use crossterm::*;
+
+lazy_static! {
+    static ref CROSSTERM: Crossterm = {
+        let mut screen = Screen::new(true);
+        screen.disable_drop();
+        Crossterm::new(&screen)
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use crate::terminal::*;
+
+    use amethyst::ecs::World;
+    use log::LevelFilter;
+
+    use std::thread::{sleep, spawn};
+    use std::time::Duration;
+
+    /// Interactive: type commands while logs scroll above the input line. Ctrl+C exits.
+    #[test]
+    #[ignore]
+    pub fn crossterm() {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        let console = TerminalConsole::with_sender(tx);
+        let mut world = World::new();
+        world.insert(CommandRegistry::with_builtins());
+        console.set_completions(
+            world
+                .fetch::<CommandRegistry>()
+                .names()
+                .iter()
+                .map(|n| n.to_string())
+                .collect(),
+        );
+
+        ConsoleLogger::new(LevelFilter::Debug)
+            .terminal(console.printer())
+            .start()
+            .unwrap_or_else(|_| {
+                error!("Global logger already set, amethyst-extra logger not used!");
+                LogBuffer::default()
+            });
+
+        spawn(|| loop {
+            info!("More random stuff");
+            sleep(Duration::from_millis(52));
+        });
+
+        loop {
+            info!("random stuff");
+            while let Ok(callback) = rx.try_recv() {
+                callback(&mut world);
+            }
+            sleep(Duration::from_millis(100));
+        }
+    }
+}
@@ -0,0 +1,260 @@
+use crate::http::WorldCallback;
+use crate::terminal::execute_command;
+
+use ::amethyst::CallbackQueue;
+use crossbeam_channel::Sender;
+
+use std::io::{self, BufRead, BufReader, ErrorKind, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread::{self, sleep, JoinHandle};
+use std::time::Duration;
+
+/// How long a client waits for the main thread to run its command.
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(10);
+/// How often the threads check if the console was stopped.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// A console reachable over tcp, to run commands in headless builds.
+///
+/// Connect using `nc 127.0.0.1 <port>`, type the password, then commands, one per line.
+/// The commands are executed on the main thread through the `CallbackQueue`, and their output is sent back.
+///
+/// The connection is not encrypted: bind it to a loopback address, or tunnel it.
+///
+/// Usage:
+/// ```rs
+/// let rcon = RemoteConsole::bind("127.0.0.1:27015", "secret", &world.read_resource::<CallbackQueue>())?;
+/// world.insert(rcon);
+/// ```
+pub struct RemoteConsole {
+    local_addr: SocketAddr,
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl RemoteConsole {
+    pub fn bind<A: ToSocketAddrs>(
+        addr: A,
+        password: &str,
+        callback_queue: &CallbackQueue,
+    ) -> io::Result<Self> {
+        Self::with_sender(addr, password, callback_queue.send_handle())
+    }
+
+    /// Sends the commands to the provided channel instead of the `CallbackQueue`.
+    pub fn with_sender<A: ToSocketAddrs>(
+        addr: A,
+        password: &str,
+        sender: Sender<WorldCallback>,
+    ) -> io::Result<Self> {
+        if password.is_empty() {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "The remote console requires a password.",
+            ));
+        }
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        let local_addr = listener.local_addr()?;
+        if !local_addr.ip().is_loopback() {
+            warn!(
+                "The remote console listens on {}, which is reachable from other computers.",
+                local_addr
+            );
+        }
+
+        let running = Arc::new(AtomicBool::new(true));
+        let thread_running = running.clone();
+        let password = password.to_string();
+        let thread = thread::spawn(move || {
+            while thread_running.load(Ordering::SeqCst) {
+                match listener.accept() {
+                    Ok((stream, peer)) => {
+                        info!("Remote console connection from {}", peer);
+                        let running = thread_running.clone();
+                        let password = password.clone();
+                        let sender = sender.clone();
+                        thread::spawn(move || {
+                            if let Err(e) = handle_client(stream, &password, &sender, &running) {
+                                warn!("Remote console connection from {} failed: {}", peer, e);
+                            }
+                        });
+                    }
+                    Err(ref e) if e.kind() == ErrorKind::WouldBlock => sleep(POLL_INTERVAL),
+                    Err(e) => {
+                        error!("Remote console stopped accepting connections: {}", e);
+                        return;
+                    }
+                }
+            }
+        });
+
+        Ok(RemoteConsole {
+            local_addr,
+            running,
+            thread: Some(thread),
+        })
+    }
+
+    /// The address the console listens on. Useful when binding port 0.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+impl Drop for RemoteConsole {
+    /// Stops accepting connections and disconnects the clients.
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Reads a line, checking regularly if the console was stopped.
+/// Returns `None` when the client disconnects or the console stops.
+fn read_line(
+    reader: &mut BufReader<TcpStream>,
+    running: &AtomicBool,
+) -> io::Result<Option<String>> {
+    let mut line = String::new();
+    loop {
+        if !running.load(Ordering::SeqCst) {
+            return Ok(None);
+        }
+        match reader.read_line(&mut line) {
+            Ok(0) => return Ok(None),
+            Ok(_) if line.ends_with('\n') => {
+                return Ok(Some(line.trim_end_matches(&['\r', '\n'][..]).to_string()))
+            }
+            Ok(_) => {}
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Compares the whole strings, so that the time taken doesn't tell how many characters are right.
+fn password_matches(given: &str, expected: &str) -> bool {
+    given.len() == expected.len()
+        && given
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+fn handle_client(
+    stream: TcpStream,
+    password: &str,
+    sender: &Sender<WorldCallback>,
+    running: &AtomicBool,
+) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(POLL_INTERVAL))?;
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+
+    writer.write_all(b"Password:\n")?;
+    match read_line(&mut reader, running)? {
+        Some(ref given) if password_matches(given, password) => {
+            writer.write_all(b"Connected.\n")?
+        }
+        Some(_) => {
+            // Slows down password guessing.
+            sleep(Duration::from_secs(1));
+            writer.write_all(b"Wrong password.\n")?;
+            return Ok(());
+        }
+        None => return Ok(()),
+    }
+
+    while let Some(line) = read_line(&mut reader, running)? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (reply_tx, reply_rx) = mpsc::channel();
+        let callback: WorldCallback = Box::new(move |world| {
+            let reply = match execute_command(world, &line) {
+                Ok(out) => out,
+                Err(e) => e.to_string(),
+            };
+            let _ = reply_tx.send(reply);
+        });
+        if sender.send(callback).is_err() {
+            writer.write_all(b"The game stopped.\n")?;
+            return Ok(());
+        }
+        match reply_rx.recv_timeout(COMMAND_TIMEOUT) {
+            Ok(ref reply) if reply.is_empty() => {}
+            Ok(reply) => writer.write_all(format!("{}\n", reply).as_bytes())?,
+            Err(_) => writer.write_all(b"The command did not run in time.\n")?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::terminal::*;
+
+    use amethyst::ecs::World;
+
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpStream;
+    use std::time::Duration;
+
+    fn read_line(reader: &mut BufReader<TcpStream>) -> String {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        line
+    }
+
+    #[test]
+    fn remote_commands() {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        let console = RemoteConsole::with_sender("127.0.0.1:0", "secret", tx).unwrap();
+        let mut world = World::new();
+        world.insert(CommandRegistry::with_builtins());
+
+        let stream = TcpStream::connect(console.local_addr()).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut writer = stream.try_clone().unwrap();
+        let mut reader = BufReader::new(stream);
+        assert_eq!(read_line(&mut reader), "Password:\n");
+        writer.write_all(b"wrong\n").unwrap();
+        assert_eq!(read_line(&mut reader), "Wrong password.\n");
+        assert_eq!(read_line(&mut reader), "");
+
+        let stream = TcpStream::connect(console.local_addr()).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut writer = stream.try_clone().unwrap();
+        let mut reader = BufReader::new(stream);
+        assert_eq!(read_line(&mut reader), "Password:\n");
+        writer.write_all(b"secret\r\n").unwrap();
+        assert_eq!(read_line(&mut reader), "Connected.\n");
+
+        writer.write_all(b"echo hello there\n").unwrap();
+        rx.recv_timeout(Duration::from_secs(5)).unwrap()(&mut world);
+        assert_eq!(read_line(&mut reader), "hello there\n");
+
+        writer.write_all(b"nope\n").unwrap();
+        rx.recv_timeout(Duration::from_secs(5)).unwrap()(&mut world);
+        assert_eq!(
+            read_line(&mut reader),
+            "Unknown command: nope. Type help for the list.\n"
+        );
+
+        assert!(
+            RemoteConsole::with_sender("127.0.0.1:0", "", crossbeam_channel::unbounded().0)
+                .is_err()
+        );
+    }
+}
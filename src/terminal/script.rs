@@ -0,0 +1,148 @@
+use crate::asset_loader::AssetLoader;
+use crate::terminal::{execute_command, CommandError};
+
+use ::amethyst::ecs::World;
+
+use std::fmt;
+use std::fs;
+
+/// An error in a command file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScriptError {
+    pub file: String,
+    /// Starts at 1. 0 when the file itself could not be read.
+    pub line: usize,
+    pub error: CommandError,
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}: {}", self.file, self.error)
+        } else {
+            write!(f, "{}:{}: {}", self.file, self.line, self.error)
+        }
+    }
+}
+
+impl std::error::Error for ScriptError {}
+
+/// The files being executed, to detect files executing themselves.
+#[derive(Debug, Default)]
+struct ExecStack(Vec<String>);
+
+/// Runs each line of the source as a console command.
+/// Empty lines and lines starting with `#` or `//` are ignored.
+/// Execution continues after a failing line, all the errors are returned.
+pub fn execute_script(world: &mut World, file: &str, source: &str) -> Result<(), Vec<ScriptError>> {
+    let mut errors = vec![];
+    for (i, line) in source.lines().enumerate() {
+        match execute_command(world, line) {
+            Ok(ref out) if out.is_empty() => {}
+            Ok(out) => info!("{}", out),
+            Err(error) => errors.push(ScriptError {
+                file: file.to_string(),
+                line: i + 1,
+                error,
+            }),
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// Executes a file of console commands.
+/// The path is resolved through the `AssetLoader` resource when there is one, so asset packs can override it.
+pub fn exec_file(world: &mut World, path: &str) -> Result<(), Vec<ScriptError>> {
+    let file_error = |error: String| {
+        vec![ScriptError {
+            file: path.to_string(),
+            line: 0,
+            error: CommandError::Failed(error),
+        }]
+    };
+    let resolved = match world.try_fetch::<AssetLoader>() {
+        Some(loader) => loader
+            .resolve_path(path)
+            .ok_or_else(|| file_error(String::from("File not found.")))?,
+        None => path.to_string(),
+    };
+    let source = fs::read_to_string(&resolved).map_err(|e| file_error(e.to_string()))?;
+
+    if !world.has_value::<ExecStack>() {
+        world.insert(ExecStack::default());
+    }
+    if world.fetch::<ExecStack>().0.contains(&resolved) {
+        return Err(file_error(String::from(
+            "The file is already being executed.",
+        )));
+    }
+    world.fetch_mut::<ExecStack>().0.push(resolved);
+    let result = execute_script(world, path, &source);
+    world.fetch_mut::<ExecStack>().0.pop();
+    result
+}
+
+/// Executes `autoexec.cfg` if it exists, logging the errors.
+/// Call it once the commands and cvars are registered, usually in the `on_start` of the first state.
+pub fn autoexec(world: &mut World) {
+    let exists = match world.try_fetch::<AssetLoader>() {
+        Some(loader) => loader.resolve_path("autoexec.cfg").is_some(),
+        None => fs::metadata("autoexec.cfg").is_ok(),
+    };
+    if !exists {
+        return;
+    }
+    if let Err(errors) = exec_file(world, "autoexec.cfg") {
+        for e in errors {
+            error!("{}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::terminal::*;
+    use crate::AssetLoader;
+
+    use amethyst::core::timing::Time;
+    use amethyst::ecs::World;
+
+    use std::fs;
+
+    #[test]
+    fn exec_resolves_overrides_and_reports_lines() {
+        let dir = std::env::temp_dir().join(format!("amethyst_extra_exec_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("base")).unwrap();
+        fs::create_dir_all(dir.join("mod1")).unwrap();
+        fs::write(dir.join("base/config.cfg"), "timescale 2\n").unwrap();
+        fs::write(
+            dir.join("mod1/config.cfg"),
+            "# Mod config\ntimescale 0.5\n\ntimescale fast\nexec config.cfg\nnope\n",
+        )
+        .unwrap();
+
+        let mut world = World::new();
+        world.insert(CommandRegistry::with_builtins());
+        world.insert(Time::default());
+        world.insert(AssetLoader::new(dir.to_str().unwrap(), "base"));
+
+        let errors = exec_file(&mut world, "config.cfg").unwrap_err();
+        assert_eq!(world.fetch::<Time>().time_scale(), 0.5);
+        assert_eq!(
+            errors.iter().map(|e| e.line).collect::<Vec<_>>(),
+            vec![4, 5, 6]
+        );
+        assert_eq!(
+            errors[2].to_string(),
+            "config.cfg:6: Unknown command: nope. Type help for the list."
+        );
+
+        assert!(exec_file(&mut world, "missing.cfg").unwrap_err()[0].line == 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
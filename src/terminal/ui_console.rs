@@ -0,0 +1,248 @@
+use crate::auto_text::UiAutoText;
+use crate::http::WorldCallback;
+use crate::terminal::{execute_command, LogBuffer, LogLine};
+
+use ::amethyst::core::HiddenPropagate;
+use ::amethyst::ecs::*;
+use ::amethyst::input::*;
+use ::amethyst::shrev::EventChannel;
+use ::amethyst::ui::{Selected, UiEvent, UiEventType, UiText};
+use ::amethyst::CallbackQueue;
+
+use log::Level;
+use serde::Serialize;
+
+use std::mem;
+
+/// A drop-down console drawn with the amethyst ui, executing the commands of the `CommandRegistry`.
+///
+/// Create the console ui and tag its entities:
+/// * `UiConsoleRoot` on the entity hidden while the console is closed (use a parent of the whole console).
+/// * `UiConsoleInput` on the editable `UiText` receiving the commands. Pressing enter runs the command.
+/// * `UiConsoleScrollback` on the `UiText` displaying the `LogBuffer`,
+/// updated by the `UiAutoTextSystem<UiConsoleScrollback>`.
+///
+/// The commands and their output are added to the `LogBuffer` resource.
+#[derive(new, Debug, Serialize, Deserialize)]
+pub struct UiConsole<T>
+where
+    T: BindingTypes,
+{
+    pub toggle_action_key: T::Action,
+    #[new(default)]
+    pub(crate) open: bool,
+}
+
+impl<T> UiConsole<T>
+where
+    T: BindingTypes,
+{
+    pub fn open(&self) -> bool {
+        self.open
+    }
+}
+
+#[derive(Default, new, Serialize, Deserialize, Clone, Copy)]
+pub struct UiConsoleRoot;
+
+impl Component for UiConsoleRoot {
+    type Storage = NullStorage<Self>;
+}
+
+#[derive(Default, new, Serialize, Deserialize, Clone, Copy)]
+pub struct UiConsoleInput;
+
+impl Component for UiConsoleInput {
+    type Storage = NullStorage<Self>;
+}
+
+/// Displays the last `lines` lines of the `LogBuffer`.
+#[derive(Debug, Clone, new)]
+pub struct UiConsoleScrollback {
+    pub buffer: LogBuffer,
+    pub lines: usize,
+}
+
+impl UiAutoText for UiConsoleScrollback {
+    fn get_text(&self) -> String {
+        self.buffer
+            .recent(self.lines)
+            .iter()
+            .map(|l| match l.level {
+                Level::Info => l.message.clone(),
+                level => format!("[{}] {}", level, l.message),
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+impl Component for UiConsoleScrollback {
+    type Storage = DenseVecStorage<Self>;
+}
+
+/// Runs the command, adding it and its output to the `LogBuffer` resource if there is one.
+pub fn execute_console_line(world: &mut World, line: &str) {
+    let push = |world: &World, level: Level, message: String| {
+        if let Some(buffer) = world.try_fetch::<LogBuffer>() {
+            buffer.push(LogLine {
+                level,
+                target: String::from("console"),
+                message,
+            });
+        }
+    };
+    push(world, Level::Info, format!("> {}", line));
+    match execute_command(world, line) {
+        Ok(ref out) if out.is_empty() => {}
+        Ok(out) => push(world, Level::Info, out),
+        Err(e) => push(world, Level::Error, e.to_string()),
+    }
+}
+
+/// Opens and closes the `UiConsole` and runs the commands entered in the `UiConsoleInput`.
+/// The commands run on the main thread through the `CallbackQueue`.
+#[derive(Debug)]
+pub struct UiConsoleSystem<T>
+where
+    T: BindingTypes,
+{
+    input_reader: ReaderId<InputEvent<T>>,
+    ui_reader: ReaderId<UiEvent>,
+}
+
+impl<T> UiConsoleSystem<T>
+where
+    T: BindingTypes,
+{
+    pub fn new(world: &mut World) -> Self {
+        <Self as System>::SystemData::setup(world);
+        let input_reader = world
+            .fetch_mut::<EventChannel<InputEvent<T>>>()
+            .register_reader();
+        let ui_reader = world.fetch_mut::<EventChannel<UiEvent>>().register_reader();
+        UiConsoleSystem {
+            input_reader,
+            ui_reader,
+        }
+    }
+}
+
+impl<'a, T> System<'a> for UiConsoleSystem<T>
+where
+    T: BindingTypes,
+{
+    type SystemData = (
+        Entities<'a>,
+        Read<'a, EventChannel<InputEvent<T>>>,
+        Read<'a, EventChannel<UiEvent>>,
+        WriteExpect<'a, UiConsole<T>>,
+        ReadExpect<'a, CallbackQueue>,
+        ReadStorage<'a, UiConsoleRoot>,
+        ReadStorage<'a, UiConsoleInput>,
+        WriteStorage<'a, UiText>,
+        WriteStorage<'a, HiddenPropagate>,
+        WriteStorage<'a, Selected>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            entities,
+            input_events,
+            ui_events,
+            mut console,
+            callbacks,
+            roots,
+            inputs,
+            mut texts,
+            mut hiddens,
+            mut selecteds,
+        ): Self::SystemData,
+    ) {
+        let mut toggled = false;
+        for event in input_events.read(&mut self.input_reader) {
+            if let InputEvent::ActionPressed(key) = event {
+                if *key == console.toggle_action_key {
+                    toggled = !toggled;
+                }
+            }
+        }
+        if toggled {
+            console.open = !console.open;
+            for (entity, _) in (&entities, &inputs).join() {
+                if console.open {
+                    selecteds
+                        .insert(entity, Selected)
+                        .expect("Failed to select the console input.");
+                } else {
+                    selecteds.remove(entity);
+                }
+            }
+        }
+
+        for (entity, _) in (&entities, &roots).join() {
+            if console.open {
+                hiddens.remove(entity);
+            } else if !hiddens.contains(entity) {
+                hiddens
+                    .insert(entity, HiddenPropagate::default())
+                    .expect("Failed to hide the console.");
+            }
+        }
+
+        for event in ui_events.read(&mut self.ui_reader) {
+            if event.event_type != UiEventType::ValueCommit || !inputs.contains(event.target) {
+                continue;
+            }
+            let line = match texts.get_mut(event.target) {
+                Some(text) => mem::replace(&mut text.text, String::new()),
+                None => continue,
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            let callback: WorldCallback = Box::new(move |world| execute_console_line(world, &line));
+            if callbacks.send_handle().send(callback).is_err() {
+                error!("Failed to send the console command to the CallbackQueue.");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::terminal::*;
+    use crate::UiAutoText;
+
+    use amethyst::ecs::World;
+    use log::Level;
+
+    #[test]
+    fn scrollback_shows_commands_and_output() {
+        let buffer = LogBuffer::new(10);
+        let mut world = World::new();
+        world.insert(CommandRegistry::with_builtins());
+        world.insert(buffer.clone());
+        buffer.push(LogLine {
+            level: Level::Warn,
+            target: String::from("game"),
+            message: String::from("low fps"),
+        });
+        execute_console_line(&mut world, "echo hello");
+        execute_console_line(&mut world, "nope");
+
+        let scrollback = UiConsoleScrollback::new(buffer, 4);
+        assert_eq!(
+            scrollback.get_text(),
+            "> echo hello\nhello\n> nope\n[ERROR] Unknown command: nope. Type help for the list."
+        );
+        assert_eq!(
+            UiConsoleScrollback::new(scrollback.buffer.clone(), 10)
+                .get_text()
+                .lines()
+                .next(),
+            Some("[WARN] low fps")
+        );
+    }
+}